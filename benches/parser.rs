@@ -0,0 +1,96 @@
+// Compares the buffered request parser with reading one byte at a time,
+// which is how requests used to be read. Run with `cargo bench`.
+use std::{
+    hint::black_box,
+    io::{BufRead, BufReader, Cursor, Read},
+    time::{Duration, Instant},
+};
+
+use airline::{
+    parser::{read_request_body, read_request_head},
+    RequestLimits,
+};
+
+const ROUNDS: usize = 20_000;
+
+fn request(body_len: usize) -> Vec<u8> {
+    let mut request = format!(
+        "POST /users/42/posts?sort=new&page=3 HTTP/1.1\r\n\
+         Host: localhost:3000\r\n\
+         User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0\r\n\
+         Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n\
+         Accept-Language: en-US,en;q=0.5\r\n\
+         Accept-Encoding: gzip, deflate, br\r\n\
+         Connection: keep-alive\r\n\
+         Content-Type: application/octet-stream\r\n\
+         Content-Length: {}\r\n\r\n",
+        body_len
+    )
+    .into_bytes();
+    request.resize(request.len() + body_len, b'x');
+    request
+}
+
+fn buffered(input: &[u8]) {
+    let limits = RequestLimits::new();
+    let mut reader = BufReader::new(Cursor::new(input));
+    let mut request = read_request_head(&mut reader, &limits).unwrap();
+    let body = read_request_body(&mut reader, &mut request, &limits, limits.max_body_bytes).unwrap();
+    black_box((request, body));
+}
+
+// The previous reader: every byte is its own read_exact call and the
+// head ends after any four CR or LF characters in a row. The head is
+// then handed to the current parser so both build the same request.
+fn byte_at_a_time(input: &[u8]) {
+    let limits = RequestLimits::new();
+    let mut reader = BufReader::new(Cursor::new(input));
+    let mut headers_content = String::new();
+    let mut cur_char: [u8; 1] = [0];
+    let mut whitespace_count = 0;
+    loop {
+        reader.read_exact(&mut cur_char).unwrap();
+        let cur_char_val = cur_char[0] as char;
+        headers_content.push(cur_char_val);
+        if cur_char_val == '\n' || cur_char_val == '\r' {
+            whitespace_count += 1;
+        } else {
+            whitespace_count = 0;
+        }
+        if whitespace_count == 4 {
+            break;
+        }
+    }
+    let request = read_request_head(&mut headers_content.as_bytes(), &limits).unwrap();
+    let content_len: usize = request.headers.get("Content-Length").unwrap().parse().unwrap();
+    let mut content: Vec<u8> = Vec::new();
+    loop {
+        if content.len() == content_len {
+            break;
+        }
+        reader.read_exact(&mut cur_char).unwrap();
+        content.push(cur_char[0]);
+    }
+    black_box((request, content, reader.fill_buf().unwrap().len()));
+}
+
+fn time(name: &str, input: &[u8], parse: fn(&[u8])) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        parse(black_box(input));
+    }
+    let elapsed = start.elapsed();
+    let mb_per_sec = (input.len() * ROUNDS) as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!("  {:<16}{:>10.2?}{:>12.1} MB/s", name, elapsed, mb_per_sec);
+    elapsed
+}
+
+fn main() {
+    for body_len in [0, 1024, 64 * 1024] {
+        let input = request(body_len);
+        println!("{} byte body, {} requests:", body_len, ROUNDS);
+        let old = time("byte at a time", &input, byte_at_a_time);
+        let new = time("buffered", &input, buffered);
+        println!("  {:.1}x faster", old.as_secs_f64() / new.as_secs_f64());
+    }
+}
@@ -52,7 +52,7 @@ fn compile_assets(assets_dir: &String, dev_mode: bool) {
             output += ".to_vec()";
         } else {
             output += "\t\treturn read(\"";
-            output += &assets_dir;
+            output += assets_dir;
             if !assets_dir.ends_with(delimiter()) {
                 output += delimiter();
             }
@@ -0,0 +1,88 @@
+use std::{env, path::PathBuf, time::Duration};
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub keep_alive: bool,
+    pub keep_alive_timeout: Duration,
+    pub max_requests_per_connection: usize,
+    pub limits: RequestLimits,
+    // How long a client has to send a complete request head, and then
+    // its body. Past either the request is answered with 408.
+    pub header_read_timeout: Duration,
+    pub body_read_timeout: Duration,
+    pub write_timeout: Duration,
+    // Defaults to half the available cores.
+    pub worker_threads: Option<usize>,
+    // Open connections beyond this are answered with 503 and closed.
+    // Each one has a thread waiting on the client while it is idle.
+    pub max_connections: usize,
+    pub multipart: MultipartOptions,
+}
+impl ServerConfig {
+    pub fn new() -> ServerConfig {
+        ServerConfig {
+            keep_alive: true,
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests_per_connection: 100,
+            limits: RequestLimits::new(),
+            header_read_timeout: Duration::from_secs(10),
+            body_read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            worker_threads: None,
+            max_connections: 1024,
+            multipart: MultipartOptions::new(),
+        }
+    }
+}
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig::new()
+    }
+}
+
+// Bounds on what a single request may send. Requests over them are
+// answered with 414, 431 or 413 without reading the rest into memory.
+#[derive(Clone, Copy)]
+pub struct RequestLimits {
+    pub max_uri_length: usize,
+    pub max_header_bytes: usize,
+    pub max_header_count: usize,
+    pub max_body_bytes: usize,
+}
+impl RequestLimits {
+    pub fn new() -> RequestLimits {
+        RequestLimits {
+            max_uri_length: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_header_count: 100,
+            max_body_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+impl Default for RequestLimits {
+    fn default() -> RequestLimits {
+        RequestLimits::new()
+    }
+}
+
+// How multipart/form-data uploads are stored. Parts larger than
+// `spill_threshold` are written to a file in `temp_dir` instead of
+// being kept in memory. Nothing is spilled unless a threshold is set.
+#[derive(Debug, Clone)]
+pub struct MultipartOptions {
+    pub spill_threshold: Option<usize>,
+    pub temp_dir: PathBuf,
+}
+impl MultipartOptions {
+    pub fn new() -> MultipartOptions {
+        MultipartOptions {
+            spill_threshold: None,
+            temp_dir: env::temp_dir(),
+        }
+    }
+}
+impl Default for MultipartOptions {
+    fn default() -> MultipartOptions {
+        MultipartOptions::new()
+    }
+}
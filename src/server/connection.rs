@@ -0,0 +1,412 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{
+    env, fs,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::Path,
+};
+
+// A client connection, whatever kind of socket it arrived on.
+// Workers only ever see this, so handlers don't need to know either.
+pub trait Connection: Read + Write + Send {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+impl Listener {
+    pub fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        match self {
+            Listener::Tcp(listener) => Ok(Box::new(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Box::new(listener.accept()?.0)),
+        }
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path, options: &UnixSocketOptions) -> io::Result<Listener> {
+        if options.remove_stale {
+            remove_stale_socket(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = options.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(Listener::Unix(listener))
+    }
+
+    // Take over a socket that is already listening, e.g. one inherited
+    // from a parent process. Whether it is TCP or Unix is worked out
+    // from the socket's own address. Anything other than a listening
+    // stream socket is refused, as every accept on it would fail.
+    #[cfg(unix)]
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Listener> {
+        check_listening(&fd)?;
+        let tcp = TcpListener::from(fd);
+        if tcp.local_addr().is_ok() {
+            return Ok(Listener::Tcp(tcp));
+        }
+        let unix = UnixListener::from(OwnedFd::from(tcp));
+        unix.local_addr()?;
+        Ok(Listener::Unix(unix))
+    }
+
+    // The sockets systemd passed to this process through socket
+    // activation, in the order they appear in the .socket unit.
+    // The variables are cleared so child processes don't claim them too.
+    #[cfg(unix)]
+    pub fn from_systemd() -> io::Result<Vec<Listener>> {
+        const SD_LISTEN_FDS_START: RawFd = 3;
+        let pid = env::var("LISTEN_PID").ok().and_then(|p| p.parse::<u32>().ok());
+        let count = env::var("LISTEN_FDS").ok().and_then(|c| c.parse::<RawFd>().ok());
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+        let count = match (pid, count) {
+            (Some(pid), Some(count)) if pid == std::process::id() && count > 0 => count,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No sockets were passed in LISTEN_FDS",
+                ))
+            }
+        };
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+            // Safety: systemd hands these descriptors to this process,
+            // and clearing LISTEN_FDS above stops them being taken twice.
+            .map(|fd| Listener::from_fd(unsafe { OwnedFd::from_raw_fd(fd) }))
+            .collect()
+    }
+}
+
+#[cfg(unix)]
+#[derive(Clone, Copy)]
+pub struct UnixSocketOptions {
+    // File mode for the socket, e.g. 0o660 so only the proxy's
+    // group can connect. Left to the umask when unset.
+    pub mode: Option<u32>,
+    // Remove a socket file left behind by a previous run.
+    pub remove_stale: bool,
+}
+#[cfg(unix)]
+impl UnixSocketOptions {
+    pub fn new() -> UnixSocketOptions {
+        UnixSocketOptions {
+            mode: None,
+            remove_stale: true,
+        }
+    }
+}
+#[cfg(unix)]
+impl Default for UnixSocketOptions {
+    fn default() -> UnixSocketOptions {
+        UnixSocketOptions::new()
+    }
+}
+
+// A socket file nobody is accepting on is left over from a server that
+// didn't shut down cleanly. Anything else at the path is left alone,
+// so binding fails instead of removing a live socket or a regular file.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Ok(());
+    }
+    match UnixStream::connect(path) {
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+// Checks a descriptor with getsockopt. The option numbers differ
+// between platforms, and where they aren't known the descriptor isn't
+// checked, so a wrong one fails on accept instead.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+mod sockopt {
+    use std::{
+        io,
+        os::{
+            fd::{AsRawFd, OwnedFd},
+            raw::{c_int, c_void},
+        },
+    };
+
+    use numbers::{SOCK_STREAM, SOL_SOCKET, SO_ACCEPTCONN, SO_TYPE};
+
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        not(any(target_arch = "mips", target_arch = "mips64"))
+    ))]
+    mod numbers {
+        use std::os::raw::c_int;
+        pub const SOL_SOCKET: c_int = 1;
+        pub const SO_TYPE: c_int = 3;
+        pub const SO_ACCEPTCONN: c_int = 30;
+        pub const SOCK_STREAM: c_int = 1;
+    }
+    // MIPS Linux keeps the numbers it inherited from IRIX.
+    #[cfg(all(
+        any(target_os = "linux", target_os = "android"),
+        any(target_arch = "mips", target_arch = "mips64")
+    ))]
+    mod numbers {
+        use std::os::raw::c_int;
+        pub const SOL_SOCKET: c_int = 0xffff;
+        pub const SO_TYPE: c_int = 0x1008;
+        pub const SO_ACCEPTCONN: c_int = 0x1009;
+        pub const SOCK_STREAM: c_int = 2;
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    mod numbers {
+        use std::os::raw::c_int;
+        pub const SOL_SOCKET: c_int = 0xffff;
+        pub const SO_TYPE: c_int = 0x1008;
+        pub const SO_ACCEPTCONN: c_int = 0x0002;
+        pub const SOCK_STREAM: c_int = 1;
+    }
+
+    extern "C" {
+        fn getsockopt(
+            socket: c_int,
+            level: c_int,
+            name: c_int,
+            value: *mut c_void,
+            len: *mut u32,
+        ) -> c_int;
+    }
+
+    fn socket_option(fd: &OwnedFd, name: c_int) -> io::Result<c_int> {
+        let mut value: c_int = 0;
+        let mut len = std::mem::size_of::<c_int>() as u32;
+        // Safety: the descriptor is owned and open, and value and len
+        // describe a buffer the size of the int being asked for.
+        let result = unsafe {
+            getsockopt(
+                fd.as_raw_fd(),
+                SOL_SOCKET,
+                name,
+                &mut value as *mut c_int as *mut c_void,
+                &mut len,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(value)
+    }
+
+    pub(super) fn check_listening(fd: &OwnedFd) -> io::Result<()> {
+        if socket_option(fd, SO_TYPE)? != SOCK_STREAM || socket_option(fd, SO_ACCEPTCONN)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "File descriptor is not a listening stream socket",
+            ));
+        }
+        Ok(())
+    }
+}
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+use sockopt::check_listening;
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))
+))]
+fn check_listening(_: &OwnedFd) -> io::Result<()> {
+    Ok(())
+}
+
+// A connection that can give up on reads at a fixed point in time.
+// A per-read timeout alone would let a client trickle in one byte at a
+// time forever, so the deadline covers the whole header or body instead.
+pub struct TimedStream {
+    stream: Box<dyn Connection>,
+    deadline: Option<Instant>,
+    // Bytes already read that are handed out again before anything
+    // else is read from the connection.
+    unread: Vec<u8>,
+    unread_pos: usize,
+}
+impl TimedStream {
+    pub fn new(stream: Box<dyn Connection>) -> TimedStream {
+        TimedStream {
+            stream,
+            deadline: None,
+            unread: Vec::new(),
+            unread_pos: 0,
+        }
+    }
+
+    // Put bytes back so they are read again, ahead of any that
+    // are still waiting to be read from an earlier call.
+    pub fn unread(&mut self, mut bytes: Vec<u8>) {
+        bytes.extend_from_slice(&self.unread[self.unread_pos..]);
+        self.unread = bytes;
+        self.unread_pos = 0;
+    }
+
+    // Take back whatever was put back and not read yet.
+    pub fn take_unread(&mut self) -> Vec<u8> {
+        let mut bytes = std::mem::take(&mut self.unread);
+        bytes.drain(..self.unread_pos);
+        self.unread_pos = 0;
+        bytes
+    }
+
+    // Reads fail with TimedOut once `timeout` has passed from now.
+    pub fn set_deadline(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    pub fn clear_deadline(&mut self) {
+        self.deadline = None;
+    }
+
+    pub fn set_write_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.stream.set_write_timeout(Some(timeout))
+    }
+}
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.unread_pos < self.unread.len() {
+            let unread = &self.unread[self.unread_pos..];
+            let read = unread.len().min(buf.len());
+            buf[..read].copy_from_slice(&unread[..read]);
+            self.unread_pos += read;
+            if self.unread_pos == self.unread.len() {
+                self.unread = Vec::new();
+                self.unread_pos = 0;
+            }
+            return Ok(read);
+        }
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::Error::from(io::ErrorKind::TimedOut));
+                }
+                Some(remaining)
+            }
+            None => None,
+        };
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.read(buf)
+    }
+}
+impl Write for TimedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        fs::File,
+        net::{TcpListener, UdpSocket},
+        os::{
+            fd::OwnedFd,
+            unix::net::{UnixListener, UnixStream},
+        },
+    };
+
+    use super::Listener;
+
+    #[test]
+    fn from_fd_takes_only_listening_stream_sockets() {
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let listener = Listener::from_fd(OwnedFd::from(tcp)).unwrap();
+        assert_eq!(listener.local_addr(), Some(addr));
+
+        let path = std::env::temp_dir().join(format!("airline-test-{}.sock", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        let unix = UnixListener::bind(&path).unwrap();
+        assert!(matches!(
+            Listener::from_fd(OwnedFd::from(unix)),
+            Ok(Listener::Unix(_))
+        ));
+        _ = std::fs::remove_file(&path);
+
+        let (connected, _peer) = UnixStream::pair().unwrap();
+        assert!(Listener::from_fd(OwnedFd::from(connected)).is_err());
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(Listener::from_fd(OwnedFd::from(udp)).is_err());
+        let file = File::open("/dev/null").unwrap();
+        assert!(Listener::from_fd(OwnedFd::from(file)).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+// Header names are matched case-insensitively, and a name can appear
+// more than once (Set-Cookie, Accept, ...). Entries keep the order
+// they were added in.
+#[derive(Debug, Clone)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.iter().any(|(k, _)| k.eq_ignore_ascii_case(key))
+    }
+
+    // Replace every value stored under this name.
+    pub fn insert<K: AsRef<str>, V: AsRef<str>>(&mut self, key: K, value: V) {
+        self.remove(key.as_ref());
+        self.append(key, value);
+    }
+
+    pub fn append<K: AsRef<str>, V: AsRef<str>>(&mut self, key: K, value: V) {
+        self.entries
+            .push((key.as_ref().to_string(), value.as_ref().to_string()));
+    }
+
+    pub fn remove(&mut self, key: &str) -> Vec<String> {
+        let mut removed = Vec::new();
+        self.entries.retain(|(k, v)| {
+            if k.eq_ignore_ascii_case(key) {
+                removed.push(v.clone());
+                return false;
+            }
+            true
+        });
+        return removed;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+impl Default for HeaderMap {
+    fn default() -> HeaderMap {
+        HeaderMap::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderMap;
+
+    #[test]
+    fn names_match_case_insensitively() {
+        let mut headers = HeaderMap::new();
+        headers.append("Content-Type", "text/plain");
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/plain"));
+        assert!(headers.contains_key("content-TYPE"));
+        assert_eq!(headers.get("Content-Length"), None);
+    }
+
+    #[test]
+    fn repeated_names_keep_their_order() {
+        let mut headers = HeaderMap::new();
+        headers.append("Accept", "text/html");
+        headers.append("Host", "example.com");
+        headers.append("accept", "application/json");
+        headers.append("ACCEPT", "*/*");
+        assert_eq!(
+            headers.get_all("Accept"),
+            ["text/html", "application/json", "*/*"]
+        );
+        assert_eq!(headers.get("Accept"), Some("text/html"));
+        assert!(headers.get_all("Cookie").is_empty());
+    }
+
+    #[test]
+    fn insert_replaces_every_value() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Host", "example.com");
+        headers.append("set-cookie", "b=2");
+        headers.insert("SET-COOKIE", "c=3");
+        assert_eq!(headers.get_all("Set-Cookie"), ["c=3"]);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.remove("set-cookie"), ["c=3"]);
+        assert_eq!(headers.get("Host"), Some("example.com"));
+    }
+}
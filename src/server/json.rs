@@ -9,37 +9,26 @@ pub struct JsonObject {
 
 impl JsonObject {
     pub fn from_string(json: String) -> JsonObject {
-        let json = json[0..json.chars().count() - 1].to_string();
+        let json = json.trim();
+        let json = json.strip_suffix('}').unwrap_or(json).to_string();
         let mut keys: HashMap<String, JsonChild> = HashMap::new();
 
         let mut current_key = String::new();
         let mut current_subkey = JsonChild::new();
 
-        let mut enumerator = json.chars().into_iter();
+        let mut enumerator = json.chars();
 
         loop {
             if let Some(c) = enumerator.next() {
                 if c == '"' {
                     // Get key content.
-                    loop {
-                        if let Some(key_content) = enumerator.next() {
-                            if key_content != '"' {
-                                current_key.push(key_content)
-                            } else {
-                                // Skip the colon
-                                enumerator.next();
-                                break;
-                            }
-                        } else {
-                            break;
-                        }
-                    }
+                    read_string(&mut enumerator, &mut current_key);
 
                     // Get value of derived key
                     let mut value_start = ' ';
                     loop {
                         if let Some(value_spacing) = enumerator.next() {
-                            if value_spacing != ' ' {
+                            if !value_spacing.is_whitespace() && value_spacing != ':' {
                                 value_start = value_spacing;
                                 break;
                             }
@@ -47,28 +36,14 @@ impl JsonObject {
                             break;
                         }
                     }
-                    current_subkey.content_type = JsonType::type_for_delimiter(value_start);
+                    current_subkey.content_type = match JsonType::type_for_delimiter(value_start) {
+                        Some(content_type) => content_type,
+                        None => break,
+                    };
 
                     // Read value
                     if current_subkey.content_type == JsonType::String {
-                        let mut last_value = '0';
-                        loop {
-                            if let Some(inner_value) = enumerator.next() {
-                                if inner_value != '"' {
-                                    current_subkey.contents.push(inner_value)
-                                } else if last_value == '\\'
-                                    && (inner_value == '"' || inner_value == '\\')
-                                {
-                                    current_subkey.contents.pop();
-                                    current_subkey.contents.push(inner_value)
-                                } else {
-                                    break;
-                                }
-                                last_value = inner_value;
-                            } else {
-                                break;
-                            }
-                        }
+                        read_string(&mut enumerator, &mut current_subkey.contents);
                     } else if current_subkey.content_type.is_primitive() {
                         // We need to add the first index to the value.
                         // Because the other types have delimeters (", {, [)
@@ -76,7 +51,7 @@ impl JsonObject {
                         current_subkey.contents.push(value_start);
                         loop {
                             if let Some(inner_value) = enumerator.next() {
-                                if inner_value != ',' {
+                                if inner_value != ',' && !inner_value.is_whitespace() {
                                     current_subkey.contents.push(inner_value)
                                 } else {
                                     break;
@@ -86,45 +61,12 @@ impl JsonObject {
                             }
                         }
                     } else if current_subkey.content_type == JsonType::Object {
-                        let mut delimiter_stack_count = 1;
-                        current_subkey.contents.push('{');
-                        loop {
-                            if let Some(inner_value) = enumerator.next() {
-                                current_subkey.contents.push(inner_value);
-                                if inner_value == '{' {
-                                    delimiter_stack_count += 1;
-                                }
-                                if inner_value == '}' {
-                                    delimiter_stack_count -= 1;
-                                    if delimiter_stack_count == 0 {
-                                        // Remove the trailing }
-                                        current_subkey.contents.pop();
-                                        break;
-                                    }
-                                }
-                            } else {
-                                break;
-                            }
+                        // Remove the trailing }
+                        if read_nested(&mut enumerator, &mut current_subkey.contents, '{', '}') {
+                            current_subkey.contents.pop();
                         }
                     } else if current_subkey.content_type == JsonType::Array {
-                        let mut delimiter_stack_count = 1;
-                        current_subkey.contents.push('[');
-                        loop {
-                            if let Some(inner_value) = enumerator.next() {
-                                current_subkey.contents.push(inner_value);
-                                if inner_value == '[' {
-                                    delimiter_stack_count += 1;
-                                }
-                                if inner_value == ']' {
-                                    delimiter_stack_count -= 1;
-                                    if delimiter_stack_count == 0 {
-                                        break;
-                                    }
-                                }
-                            } else {
-                                break;
-                            }
-                        }
+                        read_nested(&mut enumerator, &mut current_subkey.contents, '[', ']');
                     }
 
                     keys.insert(current_key, current_subkey);
@@ -229,9 +171,11 @@ pub struct JsonArray {
 impl JsonArray {
     pub fn from_string(json: String) -> JsonArray {
         let mut values: Vec<JsonChild> = Vec::new();
-        let json = json[1..json.chars().count() - 1].to_string();
+        let json = json.trim();
+        let json = json.strip_prefix('[').unwrap_or(json);
+        let json = json.strip_suffix(']').unwrap_or(json).to_string();
 
-        let mut enumerator = json.chars().into_iter().peekable();
+        let mut enumerator = json.chars().peekable();
         let mut current_value = JsonChild::new();
 
         loop {
@@ -240,7 +184,8 @@ impl JsonArray {
                 // Trim any extra whitespace
                 loop {
                     if let Some(value_spacing) = enumerator.next() {
-                        if value_spacing != ' ' {
+                        // Commas separating values are skipped too.
+                        if !value_spacing.is_whitespace() && value_spacing != ',' {
                             value_start = value_spacing;
                             break;
                         }
@@ -248,27 +193,14 @@ impl JsonArray {
                         break;
                     }
                 }
-                current_value.content_type = JsonType::type_for_delimiter(value_start);
+                current_value.content_type = match JsonType::type_for_delimiter(value_start) {
+                    Some(content_type) => content_type,
+                    // Nothing but whitespace was left.
+                    None => break,
+                };
                 // Read value
                 if current_value.content_type == JsonType::String {
-                    let mut last_value = '0';
-                    loop {
-                        if let Some(inner_value) = enumerator.next() {
-                            if inner_value != '"' {
-                                current_value.contents.push(inner_value)
-                            } else if last_value == '\\'
-                                && (inner_value == '"' || inner_value == '\\')
-                            {
-                                current_value.contents.pop();
-                                current_value.contents.push(inner_value)
-                            } else {
-                                break;
-                            }
-                            last_value = inner_value;
-                        } else {
-                            break;
-                        }
-                    }
+                    read_string(&mut enumerator, &mut current_value.contents);
                 } else if current_value.content_type.is_primitive() {
                     // We need to add the first index to the value.
                     // Because the other types have delimeters (", {, [)
@@ -276,7 +208,7 @@ impl JsonArray {
                     current_value.contents.push(value_start);
                     loop {
                         if let Some(inner_value) = enumerator.next() {
-                            if inner_value != ',' {
+                            if inner_value != ',' && !inner_value.is_whitespace() {
                                 current_value.contents.push(inner_value)
                             } else {
                                 break;
@@ -286,45 +218,12 @@ impl JsonArray {
                         }
                     }
                 } else if current_value.content_type == JsonType::Object {
-                    let mut delimiter_stack_count = 1;
-                    current_value.contents.push('{');
-                    loop {
-                        if let Some(inner_value) = enumerator.next() {
-                            current_value.contents.push(inner_value);
-                            if inner_value == '{' {
-                                delimiter_stack_count += 1;
-                            }
-                            if inner_value == '}' {
-                                delimiter_stack_count -= 1;
-                                if delimiter_stack_count == 0 {
-                                    // Remove the trailing }
-                                    current_value.contents.pop();
-                                    break;
-                                }
-                            }
-                        } else {
-                            break;
-                        }
+                    // Remove the trailing }
+                    if read_nested(&mut enumerator, &mut current_value.contents, '{', '}') {
+                        current_value.contents.pop();
                     }
                 } else if current_value.content_type == JsonType::Array {
-                    let mut delimiter_stack_count = 1;
-                    current_value.contents.push('[');
-                    loop {
-                        if let Some(inner_value) = enumerator.next() {
-                            current_value.contents.push(inner_value);
-                            if inner_value == '[' {
-                                delimiter_stack_count += 1;
-                            }
-                            if inner_value == ']' {
-                                delimiter_stack_count -= 1;
-                                if delimiter_stack_count == 0 {
-                                    break;
-                                }
-                            }
-                        } else {
-                            break;
-                        }
-                    }
+                    read_nested(&mut enumerator, &mut current_value.contents, '[', ']');
                 }
                 // Because the primitive types do not have a ending delimiter
                 // and read straight to the comma, we do not search until a comma
@@ -413,26 +312,275 @@ enum JsonType {
     Boolean,
     Object,
     Array,
+    Null,
 }
 
 impl JsonType {
     pub fn is_primitive(&self) -> bool {
-        return *self == JsonType::Number || *self == JsonType::Boolean;
+        return *self == JsonType::Number || *self == JsonType::Boolean || *self == JsonType::Null;
     }
-    pub fn type_for_delimiter(dlm: char) -> JsonType {
-        if dlm.is_digit(10) {
-            JsonType::Number
+    pub fn type_for_delimiter(dlm: char) -> Option<JsonType> {
+        if dlm.is_ascii_digit() || dlm == '-' {
+            Some(JsonType::Number)
         } else if dlm == '"' {
-            JsonType::String
+            Some(JsonType::String)
         } else if dlm == 'f' || dlm == 't' {
-            JsonType::Boolean
+            Some(JsonType::Boolean)
+        } else if dlm == 'n' {
+            Some(JsonType::Null)
         } else if dlm == '[' {
-            JsonType::Array
+            Some(JsonType::Array)
         } else if dlm == '{' {
-            JsonType::Object
+            Some(JsonType::Object)
         } else {
-            panic!("Unexpected value {}", dlm);
+            None
+        }
+    }
+}
+
+// Read the rest of a string whose opening quote was already read,
+// decoding its escapes. "\uXXXX" escapes are UTF-16, so characters
+// outside the BMP are sent as a pair of them.
+fn read_string(enumerator: &mut impl Iterator<Item = char>, contents: &mut String) {
+    const REPLACEMENT: char = char::REPLACEMENT_CHARACTER;
+    // The first half of a surrogate pair, waiting for the second.
+    let mut high_surrogate: Option<u32> = None;
+    loop {
+        let c = match enumerator.next() {
+            Some('"') | None => break,
+            Some('\\') => match enumerator.next() {
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('u') => {
+                    let hex = enumerator.by_ref().take(4).collect::<String>();
+                    let unit = u32::from_str_radix(&hex, 16).unwrap_or(0xFFFD);
+                    if let Some(high) = high_surrogate.take() {
+                        if (0xDC00..0xE000).contains(&unit) {
+                            let c = 0x10000 + ((high - 0xD800) << 10) + (unit - 0xDC00);
+                            contents.push(char::from_u32(c).unwrap_or(REPLACEMENT));
+                            continue;
+                        }
+                        contents.push(REPLACEMENT);
+                    }
+                    if (0xD800..0xDC00).contains(&unit) {
+                        high_surrogate = Some(unit);
+                        continue;
+                    }
+                    char::from_u32(unit).unwrap_or(REPLACEMENT)
+                }
+                // Quotes, backslashes and slashes stand for themselves.
+                Some(c) => c,
+                None => break,
+            },
+            Some(c) => c,
+        };
+        // Half a surrogate pair is not a character.
+        if high_surrogate.take().is_some() {
+            contents.push(REPLACEMENT);
         }
+        contents.push(c);
+    }
+    if high_surrogate.is_some() {
+        contents.push(REPLACEMENT);
+    }
+}
+
+// Copy an object or array whose opening bracket was already read, up
+// to and including its closing bracket. Brackets inside strings don't
+// count. Returns whether the closing bracket was found.
+fn read_nested(
+    enumerator: &mut impl Iterator<Item = char>,
+    contents: &mut String,
+    open: char,
+    close: char,
+) -> bool {
+    contents.push(open);
+    let mut depth = 1;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in enumerator {
+        contents.push(c);
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+// The parsers above assume well formed input, so request bodies are
+// checked against the JSON grammar before they are handed over.
+pub(crate) fn check_syntax(json: &str) -> Result<(), &'static str> {
+    let mut checker = SyntaxChecker {
+        bytes: json.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    checker.value()?;
+    checker.whitespace();
+    if checker.pos != checker.bytes.len() {
+        return Err("Unexpected data after JSON value");
+    }
+    return Ok(());
+}
+
+struct SyntaxChecker<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+impl SyntaxChecker<'_> {
+    // Deeper nesting is refused rather than risking the stack.
+    const MAX_DEPTH: usize = 128;
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<(), &'static str> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(b'}', true),
+            Some(b'[') => self.nested(b']', false),
+            Some(b'"') => self.string(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal(b"true"),
+            Some(b'f') => self.literal(b"false"),
+            Some(b'n') => self.literal(b"null"),
+            Some(_) => Err("Unexpected character in JSON"),
+            None => Err("Unexpected end of JSON"),
+        }
+    }
+
+    // Objects and arrays are comma separated members between brackets,
+    // object members being a string key, a colon and a value.
+    fn nested(&mut self, close: u8, object: bool) -> Result<(), &'static str> {
+        self.depth += 1;
+        if self.depth > SyntaxChecker::MAX_DEPTH {
+            return Err("JSON is nested too deeply");
+        }
+        self.pos += 1;
+        self.whitespace();
+        if !self.expect(close) {
+            loop {
+                if object {
+                    self.whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err("Expected a JSON key");
+                    }
+                    self.string()?;
+                    self.whitespace();
+                    if !self.expect(b':') {
+                        return Err("Expected ':' after JSON key");
+                    }
+                }
+                self.value()?;
+                self.whitespace();
+                if self.expect(close) {
+                    break;
+                }
+                if !self.expect(b',') {
+                    return Err("Expected ',' between JSON values");
+                }
+            }
+        }
+        self.depth -= 1;
+        return Ok(());
+    }
+
+    fn string(&mut self) -> Result<(), &'static str> {
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                            self.pos += 1
+                        }
+                        Some(b'u') => {
+                            let hex = self.bytes.get(self.pos + 1..self.pos + 5);
+                            if !hex.is_some_and(|h| h.iter().all(u8::is_ascii_hexdigit)) {
+                                return Err("Invalid escape in JSON string");
+                            }
+                            self.pos += 5;
+                        }
+                        _ => return Err("Invalid escape in JSON string"),
+                    }
+                }
+                Some(c) if c < 0x20 => return Err("Control character in JSON string"),
+                Some(_) => self.pos += 1,
+                None => return Err("Unterminated JSON string"),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<(), &'static str> {
+        self.expect(b'-');
+        if !self.digits() {
+            return Err("Invalid JSON number");
+        }
+        if self.expect(b'.') && !self.digits() {
+            return Err("Invalid JSON number");
+        }
+        if self.expect(b'e') || self.expect(b'E') {
+            _ = self.expect(b'+') || self.expect(b'-');
+            if !self.digits() {
+                return Err("Invalid JSON number");
+            }
+        }
+        return Ok(());
+    }
+
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        return self.pos > start;
+    }
+
+    fn literal(&mut self, word: &[u8]) -> Result<(), &'static str> {
+        if !self.bytes[self.pos..].starts_with(word) {
+            return Err("Unexpected character in JSON");
+        }
+        self.pos += word.len();
+        return Ok(());
     }
 }
 
@@ -527,4 +675,101 @@ impl<K: AsRef<str>, V: ToJson> ToJson for HashMap<K, V> {
         output += "}";
         return output;
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{check_syntax, JsonArray, JsonObject};
+
+    fn object(json: &str) -> JsonObject {
+        assert_eq!(check_syntax(json), Ok(()), "{}", json);
+        JsonObject::from_string(json.to_string())
+    }
+
+    #[test]
+    fn decodes_string_escapes() {
+        let json = object(r#"{"a":"\u00e9\\","b":"say \"hi\"\n","c\"d":"\/\t","e":"😀"}"#);
+        assert_eq!(json.string("a").as_deref(), Some("é\\"));
+        assert_eq!(json.string("b").as_deref(), Some("say \"hi\"\n"));
+        assert_eq!(json.string("c\"d").as_deref(), Some("/\t"));
+        assert_eq!(json.string("e").as_deref(), Some("😀"));
+        // Half a surrogate pair can't be decoded.
+        let json = object(r#"{"a":"\ud83dx","b":"\ude00"}"#);
+        assert_eq!(json.string("a").as_deref(), Some("\u{fffd}x"));
+        assert_eq!(json.string("b").as_deref(), Some("\u{fffd}"));
+    }
+
+    #[test]
+    fn nested_values_ignore_brackets_in_strings() {
+        let json = object(r#"{"o":{"s":"}{","n":{"x":1}},"a":[["]"],{"k":"v"}],"after":2}"#);
+        let inner = json.object("o").unwrap();
+        assert_eq!(inner.string("s").as_deref(), Some("}{"));
+        assert_eq!(inner.object("n").unwrap().i32("x"), Some(1));
+        let array = json.array("a").unwrap();
+        assert_eq!(array.all().len(), 2);
+        assert_eq!(
+            array.get(0).array().unwrap().get(0).string().as_deref(),
+            Some("]")
+        );
+        assert_eq!(
+            array.get(1).object().unwrap().string("k").as_deref(),
+            Some("v")
+        );
+        assert_eq!(json.i32("after"), Some(2));
+    }
+
+    #[test]
+    fn reads_null_and_negative_numbers() {
+        let json = object(r#"{"n":null,"i":-12,"f":-0.5e1,"t":true}"#);
+        assert_eq!(json.i32("n"), None);
+        assert_eq!(json.i32("i"), Some(-12));
+        assert_eq!(json.f64("f"), Some(-5.0));
+        assert_eq!(json.string("t").as_deref(), Some("true"));
+        let array = JsonArray::from_string("[null,-1,\"x\"]".to_string());
+        assert_eq!(array.all().len(), 3);
+        assert_eq!(array.get(1).i32(), Some(-1));
+        assert_eq!(array.get(2).string().as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn allows_whitespace_between_tokens() {
+        let json = object(" {\n\t\"a\" : 1 ,\r\n \"b\" : [ 1 , \"x, y\" ] , \"c\" : { } }\n");
+        assert_eq!(json.i32("a"), Some(1));
+        let array = json.array("b").unwrap();
+        assert_eq!(array.all().len(), 2);
+        assert_eq!(array.get(0).i32(), Some(1));
+        assert_eq!(array.get(1).string().as_deref(), Some("x, y"));
+        assert!(json.object("c").is_some());
+    }
+
+    #[test]
+    fn checks_syntax() {
+        for valid in [
+            r#"{}"#,
+            r#"[]"#,
+            r#"{"a":[1,2.5,-3e2,true,false,null]}"#,
+            r#""é\"""#,
+        ] {
+            assert_eq!(check_syntax(valid), Ok(()), "{}", valid);
+        }
+        for invalid in [
+            "",
+            "{",
+            r#"{"a"}"#,
+            r#"{"a":1,}"#,
+            r#"{a:1}"#,
+            r#"[1 2]"#,
+            r#"[01.]"#,
+            r#""\x""#,
+            r#""\u12g4""#,
+            "\"tab\there\"",
+            r#"{} {}"#,
+            "nul",
+        ] {
+            assert!(check_syntax(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            check_syntax(&"[".repeat(200)),
+            Err("JSON is nested too deeply")
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use super::{ResponseStatusCode, RouteError};
+
+// A parsed Content-Type such as `application/ld+json; charset=utf-8`.
+// Types and parameter names are lowercased, parameter values are kept
+// as sent. The subtype includes any suffix ("ld+json"), which is also
+// split out on its own ("json").
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType {
+    pub main_type: String,
+    pub subtype: String,
+    pub suffix: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+impl MediaType {
+    pub fn parse(header: &str) -> Option<MediaType> {
+        let (value, params) = parse_header_params(header);
+        let (main_type, subtype) = value.split_once('/')?;
+        let main_type = main_type.trim().to_ascii_lowercase();
+        let subtype = subtype.trim().to_ascii_lowercase();
+        if main_type.is_empty() || subtype.is_empty() {
+            return None;
+        }
+        let suffix = subtype
+            .rsplit_once('+')
+            .map(|(_, suffix)| suffix.to_string())
+            .filter(|suffix| !suffix.is_empty());
+        Some(MediaType {
+            main_type,
+            subtype,
+            suffix,
+            params,
+        })
+    }
+
+    // The type without parameters, e.g. "text/plain".
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.main_type, self.subtype)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.params
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn charset(&self) -> Option<String> {
+        self.param("charset").map(|c| c.to_ascii_lowercase())
+    }
+
+    // Match against a pattern such as "application/json" or "image/*".
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        match pattern.strip_suffix("/*") {
+            Some(main_type) => main_type == "*" || self.main_type == main_type,
+            None => self.essence() == pattern,
+        }
+    }
+}
+
+// Turn a text body into a String using its declared charset.
+// Bodies without one are expected to be UTF-8.
+pub(crate) fn decode_text(data: &[u8], charset: Option<&str>) -> Result<String, RouteError> {
+    match charset.unwrap_or("utf-8") {
+        "utf-8" | "utf8" | "us-ascii" | "ascii" => std::str::from_utf8(data)
+            .map(|text| text.to_string())
+            .or(Err(RouteError::bad_request("Request body is not valid UTF-8"))),
+        // Every byte is the code point of the same value.
+        "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "l1" => {
+            Ok(data.iter().map(|b| *b as char).collect())
+        }
+        "utf-16" => match data.get(..2) {
+            Some([0xFF, 0xFE]) => decode_utf16(&data[2..], false),
+            Some([0xFE, 0xFF]) => decode_utf16(&data[2..], true),
+            // Without a byte order mark UTF-16 is big endian.
+            _ => decode_utf16(data, true),
+        },
+        "utf-16be" => decode_utf16(data, true),
+        "utf-16le" => decode_utf16(data, false),
+        _ => Err(RouteError {
+            message: "Unsupported charset".to_string(),
+            status_code: ResponseStatusCode::UnsupportedMediaType,
+            override_output: false,
+        }),
+    }
+}
+
+fn decode_utf16(data: &[u8], big_endian: bool) -> Result<String, RouteError> {
+    let invalid = RouteError::bad_request("Request body is not valid UTF-16");
+    if !data.len().is_multiple_of(2) {
+        return Err(invalid);
+    }
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| {
+            if big_endian {
+                u16::from_be_bytes([pair[0], pair[1]])
+            } else {
+                u16::from_le_bytes([pair[0], pair[1]])
+            }
+        })
+        .collect();
+    let text = String::from_utf16(&units).or(Err(invalid))?;
+    // A byte order mark that survived an explicit LE or BE label.
+    return Ok(text.strip_prefix('\u{feff}').map(|t| t.to_string()).unwrap_or(text));
+}
+
+// Split a header such as `form-data; name="file"; filename="a.txt"`
+// into its value and parameters. Parameter names are lowercased and
+// quoted values unescaped.
+pub(crate) fn parse_header_params(header: &str) -> (String, Vec<(String, String)>) {
+    let mut segments: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = header.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            '\\' if in_quotes => {
+                current.push(c);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            ';' if !in_quotes => segments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current);
+
+    let value = segments[0].trim().to_string();
+    let params = segments[1..]
+        .iter()
+        .filter_map(|segment| {
+            let (key, value) = segment.split_once('=')?;
+            Some((key.trim().to_ascii_lowercase(), unquote(value.trim())))
+        })
+        .collect();
+    return (value, params);
+}
+
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+    else {
+        return value.to_string();
+    };
+    let mut output = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                output.push(escaped);
+            }
+        } else {
+            output.push(c);
+        }
+    }
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ResponseStatusCode;
+    use super::{decode_text, MediaType};
+
+    #[test]
+    fn parses_quoted_params() {
+        let media_type =
+            MediaType::parse(r#"Multipart/Form-Data; Boundary="a;b\"c"; charset=UTF-8"#).unwrap();
+        assert_eq!(media_type.essence(), "multipart/form-data");
+        assert_eq!(media_type.param("boundary"), Some(r#"a;b"c"#));
+        assert_eq!(media_type.param("Charset"), Some("UTF-8"));
+        assert_eq!(media_type.charset().as_deref(), Some("utf-8"));
+        assert_eq!(MediaType::parse("text"), None);
+        assert_eq!(MediaType::parse("/plain"), None);
+    }
+
+    #[test]
+    fn splits_out_suffixes() {
+        let media_type = MediaType::parse("application/ld+json").unwrap();
+        assert_eq!(media_type.subtype, "ld+json");
+        assert_eq!(media_type.suffix.as_deref(), Some("json"));
+        assert_eq!(MediaType::parse("application/json").unwrap().suffix, None);
+        assert_eq!(MediaType::parse("application/foo+").unwrap().suffix, None);
+    }
+
+    #[test]
+    fn matches_exact_and_wildcard_patterns() {
+        let media_type = MediaType::parse("image/png; q=1").unwrap();
+        assert!(media_type.matches("image/png"));
+        assert!(media_type.matches(" Image/PNG "));
+        assert!(media_type.matches("image/*"));
+        assert!(media_type.matches("*/*"));
+        assert!(!media_type.matches("image/jpeg"));
+        assert!(!media_type.matches("text/*"));
+    }
+
+    #[test]
+    fn decodes_charsets() {
+        assert_eq!(decode_text(b"caf\xe9", Some("iso-8859-1")).unwrap(), "café");
+        assert_eq!(decode_text(b"caf\xc3\xa9", None).unwrap(), "café");
+        assert!(decode_text(b"caf\xe9", None).is_err());
+
+        let big_endian = [0x00, 0x68, 0x00, 0xe9];
+        let little_endian = [0xff, 0xfe, 0x68, 0x00, 0xe9, 0x00];
+        assert_eq!(decode_text(&big_endian, Some("utf-16")).unwrap(), "hé");
+        assert_eq!(decode_text(&little_endian, Some("utf-16")).unwrap(), "hé");
+        assert_eq!(
+            decode_text(&[0xfe, 0xff, 0x00, 0x68], Some("utf-16")).unwrap(),
+            "h"
+        );
+        assert_eq!(decode_text(&little_endian, Some("utf-16le")).unwrap(), "hé");
+        let odd_length = decode_text(&[0x00, 0x68, 0x00], Some("utf-16be")).unwrap_err();
+        assert!(matches!(
+            odd_length.status_code,
+            ResponseStatusCode::BadRequest
+        ));
+
+        let unknown = decode_text(b"abc", Some("koi8-r")).unwrap_err();
+        assert!(matches!(
+            unknown.status_code,
+            ResponseStatusCode::UnsupportedMediaType
+        ));
+    }
+}
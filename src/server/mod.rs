@@ -1,9 +1,32 @@
+#![allow(clippy::needless_return, clippy::while_let_loop, clippy::module_inception)]
+
+pub mod config;
+pub mod connection;
+pub mod date;
+pub mod headers;
 pub mod json;
+pub mod media_type;
+pub mod multipart;
+pub mod parser;
+pub mod query;
 pub mod queue;
 pub mod reqres;
+pub mod router;
 pub mod server;
+pub mod stream;
 
+pub use config::{MultipartOptions, RequestLimits, ServerConfig};
+pub use connection::{Connection, Listener, TimedStream};
+#[cfg(unix)]
+pub use connection::UnixSocketOptions;
+pub use headers::HeaderMap;
 pub use json::{JsonArray, JsonChild, JsonObject, ToJson};
+pub use media_type::MediaType;
+pub use multipart::{Multipart, MultipartError, MultipartPart};
+pub use parser::ParseError;
+pub use query::QueryMap;
 pub use queue::{RequestQueue, WorkerSetupFn};
 pub use reqres::{BodyContents, Request, RequestType, Response, ResponseStatusCode, RouteError};
-pub use server::{Route, Server, IncomingRequest, ToBytes};
\ No newline at end of file
+pub use router::RouterError;
+pub use server::{Route, RouteHandler, Server, IncomingRequest, ToBytes};
+pub use stream::{ChunkedWriter, ResponseStream};
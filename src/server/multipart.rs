@@ -0,0 +1,503 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{
+    media_type::parse_header_params, HeaderMap, MultipartOptions, ResponseStatusCode, RouteError,
+};
+
+// A multipart/form-data body, as sent by browsers for forms with
+// file inputs. Parts are kept in the order they were sent.
+#[derive(Debug)]
+pub struct Multipart {
+    parts: Vec<MultipartPart>,
+}
+impl Multipart {
+    pub fn new() -> Multipart {
+        Multipart { parts: Vec::new() }
+    }
+
+    pub fn parse(
+        data: &[u8],
+        boundary: &str,
+        options: &MultipartOptions,
+    ) -> Result<Multipart, MultipartError> {
+        Multipart::read_from(data, boundary, options)
+    }
+
+    // Each part starts with "--boundary" on its own line, followed by
+    // its headers, a blank line and its content. "--boundary--" ends
+    // the body. Anything before the first boundary is ignored, and
+    // nothing is read after the last one. Parts are parsed as they are
+    // read, so a part over the spill threshold is written to its file
+    // as it arrives rather than held in memory.
+    pub fn read_from<R: Read>(
+        source: R,
+        boundary: &str,
+        options: &MultipartOptions,
+    ) -> Result<Multipart, MultipartError> {
+        if boundary.is_empty() {
+            return Err(MultipartError::Malformed("Missing multipart boundary"));
+        }
+        let delimiter = format!("--{}", boundary).into_bytes();
+        // Every boundary but a leading one follows a line break,
+        // which belongs to the boundary rather than the part before it.
+        let next_delimiter = format!("\r\n--{}", boundary).into_bytes();
+        let mut input = Input::new(source);
+        let start = input.find(&delimiter, "Missing multipart boundary")?;
+        input.consume(start + delimiter.len());
+        let mut parts = Vec::new();
+        loop {
+            input.want(2, "Missing closing boundary")?;
+            if input.buffer.starts_with(b"--") {
+                break;
+            }
+            let line_end = input.find(b"\r\n", "Missing closing boundary")?;
+            // Senders may pad the boundary line with whitespace.
+            if !input.buffer[..line_end]
+                .iter()
+                .all(|b| *b == b' ' || *b == b'\t')
+            {
+                return Err(MultipartError::Malformed("Malformed multipart boundary"));
+            }
+            input.consume(line_end + 2);
+
+            input.want(2, "Malformed part headers")?;
+            let headers = if input.buffer.starts_with(b"\r\n") {
+                input.consume(2);
+                HeaderMap::new()
+            } else {
+                let head_end = input.find(b"\r\n\r\n", "Malformed part headers")?;
+                let headers = parse_part_headers(&input.buffer[..head_end])?;
+                input.consume(head_end + 4);
+                headers
+            };
+
+            let mut data = PartData::Memory(Vec::new());
+            loop {
+                if let Some(end) = find(&input.buffer, &next_delimiter, 0) {
+                    data.write(&input.buffer[..end], options)?;
+                    input.consume(end + next_delimiter.len());
+                    break;
+                }
+                // Everything but what could be the start of the
+                // next boundary belongs to this part.
+                let content = input.buffer.len().saturating_sub(next_delimiter.len() - 1);
+                data.write(&input.buffer[..content], options)?;
+                input.consume(content);
+                if !input.fill()? {
+                    return Err(MultipartError::Malformed("Missing closing boundary"));
+                }
+            }
+            parts.push(MultipartPart::new(headers, data.finish()));
+        }
+        return Ok(Multipart { parts });
+    }
+
+    pub fn parts(&self) -> &Vec<MultipartPart> {
+        return &self.parts;
+    }
+
+    pub fn into_parts(self) -> Vec<MultipartPart> {
+        self.parts
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MultipartPart> {
+        self.parts.iter().find(|p| p.name.as_deref() == Some(name))
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&MultipartPart> {
+        self.parts
+            .iter()
+            .filter(|p| p.name.as_deref() == Some(name))
+            .collect()
+    }
+
+    // Parts sent from a file input.
+    pub fn files(&self) -> Vec<&MultipartPart> {
+        self.parts.iter().filter(|p| p.filename.is_some()).collect()
+    }
+
+    pub fn string(&self, name: &str) -> Option<String> {
+        self.get(name)?.text()
+    }
+
+    pub fn validate_string(&self, name: &str, err: &str) -> Result<String, RouteError> {
+        self.string(name).ok_or(RouteError::bad_request(err))
+    }
+}
+impl Default for Multipart {
+    fn default() -> Multipart {
+        Multipart::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct MultipartPart {
+    pub headers: HeaderMap,
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    data: PartData,
+}
+impl MultipartPart {
+    fn new(headers: HeaderMap, data: PartData) -> MultipartPart {
+        let mut name = None;
+        let mut filename = None;
+        if let Some(disposition) = headers.get("Content-Disposition") {
+            let (_, params) = parse_header_params(disposition);
+            for (key, value) in params {
+                match key.as_str() {
+                    "name" => name = Some(value),
+                    "filename" => filename = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        MultipartPart {
+            content_type: headers.get("Content-Type").map(|c| c.to_string()),
+            headers,
+            name,
+            filename,
+            data,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.data {
+            PartData::Memory(data) => data.len(),
+            PartData::File(file) => file.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The content, if it was kept in memory.
+    pub fn data(&self) -> Option<&[u8]> {
+        match &self.data {
+            PartData::Memory(data) => Some(data),
+            PartData::File(_) => None,
+        }
+    }
+
+    // Where the content was written, if it was too large to keep in memory.
+    // The file is removed when the part is dropped unless it is persisted.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            PartData::Memory(_) => None,
+            PartData::File(file) => file.path.as_deref(),
+        }
+    }
+
+    // The content, wherever it is stored.
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(data) => Ok(data.clone()),
+            PartData::File(file) => fs::read(file.path.as_deref().unwrap_or(Path::new(""))),
+        }
+    }
+
+    pub fn text(&self) -> Option<String> {
+        String::from_utf8(self.bytes().ok()?).ok()
+    }
+
+    // Keep the content at `path`, moving the temporary file when there is one.
+    pub fn persist(self, path: impl AsRef<Path>) -> io::Result<()> {
+        match self.data {
+            PartData::Memory(data) => fs::write(path, data),
+            PartData::File(mut file) => {
+                let temp_path = file.path.take().unwrap_or_default();
+                // A rename can't cross filesystems, fall back to copying.
+                if fs::rename(&temp_path, &path).is_err() {
+                    let copied = fs::copy(&temp_path, &path);
+                    _ = fs::remove_file(&temp_path);
+                    copied?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+impl PartData {
+    // Add to the content, moving it to a temporary file once it grows
+    // past the spill threshold.
+    fn write(&mut self, bytes: &[u8], options: &MultipartOptions) -> io::Result<()> {
+        match self {
+            PartData::Memory(data) => {
+                data.extend_from_slice(bytes);
+                if options.spill_threshold.is_some_and(|t| data.len() > t) {
+                    *self = PartData::File(TempFile::create(&options.temp_dir, data)?);
+                }
+                Ok(())
+            }
+            PartData::File(file) => file.write(bytes),
+        }
+    }
+
+    // Close the temporary file once the content is complete.
+    fn finish(mut self) -> PartData {
+        if let PartData::File(file) = &mut self {
+            file.file = None;
+        }
+        self
+    }
+}
+
+#[derive(Debug)]
+struct TempFile {
+    path: Option<PathBuf>,
+    // Open while the part is still being read.
+    file: Option<File>,
+    len: usize,
+}
+impl TempFile {
+    fn create(dir: &Path, content: &[u8]) -> io::Result<TempFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let path = dir.join(format!(
+            "airline-upload-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = options.open(&path)?;
+        // Once created the file is ours to clean up, even if writing fails.
+        let mut temp_file = TempFile {
+            path: Some(path),
+            file: Some(file),
+            len: 0,
+        };
+        temp_file.write(content)?;
+        Ok(temp_file)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.write_all(bytes)?;
+            self.len += bytes.len();
+        }
+        Ok(())
+    }
+}
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MultipartError {
+    Malformed(&'static str),
+    // Storing a part failed.
+    Io(io::Error),
+    // Reading the body failed.
+    Read(io::Error),
+}
+impl Display for MultipartError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Malformed(message) => f.write_str(message),
+            MultipartError::Io(error) => write!(f, "Could not store upload: {}", error),
+            MultipartError::Read(error) => write!(f, "Could not read body: {}", error),
+        }
+    }
+}
+impl std::error::Error for MultipartError {}
+impl From<io::Error> for MultipartError {
+    fn from(error: io::Error) -> MultipartError {
+        MultipartError::Io(error)
+    }
+}
+impl From<MultipartError> for RouteError {
+    fn from(error: MultipartError) -> RouteError {
+        match error {
+            MultipartError::Malformed(message) => RouteError::bad_request(message),
+            MultipartError::Read(_) => RouteError::bad_request("Could not read body"),
+            MultipartError::Io(_) => RouteError {
+                message: "Could not store upload".to_string(),
+                status_code: ResponseStatusCode::InternalServerError,
+                override_output: false,
+            },
+        }
+    }
+}
+
+// The part of a body that has been read but not parsed yet.
+struct Input<R: Read> {
+    source: R,
+    buffer: Vec<u8>,
+}
+impl<R: Read> Input<R> {
+    fn new(source: R) -> Input<R> {
+        Input {
+            source,
+            buffer: Vec::new(),
+        }
+    }
+
+    // Read more of the body. False once it has all been read.
+    fn fill(&mut self) -> Result<bool, MultipartError> {
+        let mut chunk = [0; 8192];
+        let read = self.source.read(&mut chunk).map_err(MultipartError::Read)?;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    // Read until at least `len` bytes are buffered.
+    fn want(&mut self, len: usize, missing: &'static str) -> Result<(), MultipartError> {
+        while self.buffer.len() < len {
+            if !self.fill()? {
+                return Err(MultipartError::Malformed(missing));
+            }
+        }
+        Ok(())
+    }
+
+    // Read until `needle` is buffered and return where it starts.
+    fn find(&mut self, needle: &[u8], missing: &'static str) -> Result<usize, MultipartError> {
+        let mut from = 0;
+        loop {
+            if let Some(ix) = find(&self.buffer, needle, from) {
+                return Ok(ix);
+            }
+            // Only the end of what was searched could start a match.
+            from = self.buffer.len().saturating_sub(needle.len() - 1);
+            if !self.fill()? {
+                return Err(MultipartError::Malformed(missing));
+            }
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buffer.drain(..len);
+    }
+}
+
+fn parse_part_headers(data: &[u8]) -> Result<HeaderMap, MultipartError> {
+    let text =
+        std::str::from_utf8(data).or(Err(MultipartError::Malformed("Malformed part headers")))?;
+    let mut headers = HeaderMap::new();
+    for line in text.split("\r\n") {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or(MultipartError::Malformed("Malformed part headers"))?;
+        headers.append(key.trim(), value.trim());
+    }
+    return Ok(headers);
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|ix| ix + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MultipartOptions;
+    use super::{Multipart, MultipartError};
+    use std::io::{self, Read};
+
+    const BODY: &[u8] = b"preamble\r\n--xyz  \r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        hello\r\n--xyz\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line one\r\n--xy not yet\r\nline two\r\n--xyz\r\n\
+        \r\nno headers\r\n--xyz--\r\nepilogue";
+
+    // Hands out one byte per read, so every boundary is split.
+    struct Trickle<'a>(&'a [u8]);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    fn check(multipart: &Multipart) {
+        assert_eq!(multipart.parts().len(), 3);
+        assert_eq!(multipart.string("title").as_deref(), Some("hello"));
+        let file = multipart.get("file").unwrap();
+        assert_eq!(file.filename.as_deref(), Some("a.txt"));
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(
+            file.text().as_deref(),
+            Some("line one\r\n--xy not yet\r\nline two")
+        );
+        assert_eq!(multipart.parts()[2].text().as_deref(), Some("no headers"));
+    }
+
+    #[test]
+    fn parses_parts_however_they_are_read() {
+        let options = MultipartOptions::new();
+        check(&Multipart::parse(BODY, "xyz", &options).unwrap());
+        check(&Multipart::read_from(Trickle(BODY), "xyz", &options).unwrap());
+    }
+
+    #[test]
+    fn spills_large_parts_as_they_arrive() {
+        let mut options = MultipartOptions::new();
+        options.spill_threshold = Some(8);
+        options.temp_dir = std::env::temp_dir();
+        let multipart = Multipart::read_from(Trickle(BODY), "xyz", &options).unwrap();
+        check(&multipart);
+        let file = multipart.get("file").unwrap();
+        assert!(file.data().is_none());
+        let path = file.path().unwrap().to_path_buf();
+        assert_eq!(file.len(), std::fs::metadata(&path).unwrap().len() as usize);
+        assert_eq!(multipart.get("title").unwrap().data(), Some(&b"hello"[..]));
+        drop(multipart);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        let options = MultipartOptions::new();
+        let malformed =
+            |body: &[u8], boundary: &str| match Multipart::parse(body, boundary, &options) {
+                Err(MultipartError::Malformed(message)) => message,
+                other => panic!("expected malformed body, got {:?}", other),
+            };
+        assert_eq!(malformed(BODY, ""), "Missing multipart boundary");
+        assert_eq!(
+            malformed(b"no boundary here", "xyz"),
+            "Missing multipart boundary"
+        );
+        assert_eq!(
+            malformed(b"--xyz\r\n\r\nunterminated", "xyz"),
+            "Missing closing boundary"
+        );
+        assert_eq!(
+            malformed(b"--xyz junk\r\n\r\nx\r\n--xyz--", "xyz"),
+            "Malformed multipart boundary"
+        );
+        assert_eq!(
+            malformed(b"--xyz\r\nno colon\r\n\r\nx\r\n--xyz--", "xyz"),
+            "Malformed part headers"
+        );
+    }
+}
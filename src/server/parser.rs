@@ -0,0 +1,676 @@
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    io::{self, BufRead, Read, Take},
+};
+
+use super::{
+    query::percent_decode, HeaderMap, MediaType, MultipartOptions, QueryMap, Request, RequestLimits, RequestType,
+    Response, ResponseStatusCode, RouteError,
+};
+
+// Read the request line and headers. The body is read separately once
+// the request has been routed, since the route decides how large
+// a body it accepts.
+pub fn read_request_head<R: BufRead>(
+    buffer: &mut R,
+    limits: &RequestLimits,
+) -> Result<Request, ParseError> {
+    let mut head_bytes: Vec<u8> = Vec::new();
+    let mut req: Vec<String> = Vec::new();
+
+    // Pull whole lines out of the reader's buffer until the blank line
+    // ending the headers. Lines may end in CRLF or a bare LF.
+    loop {
+        let start = head_bytes.len();
+        let allowed = (limits.max_header_bytes + 1 - start) as u64;
+        let read = buffer.by_ref().take(allowed).read_until(b'\n', &mut head_bytes)?;
+        if head_bytes.len() > limits.max_header_bytes {
+            // Still on the request line means the URI is to blame.
+            if req.is_empty() {
+                return Err(ParseError::UriTooLong);
+            }
+            return Err(ParseError::HeadersTooLarge);
+        }
+        if read == 0 || head_bytes.last() != Some(&b'\n') {
+            // The client went away before finishing the request.
+            return Err(ParseError::ConnectionClosed);
+        }
+        let line = trim_line_ending(&head_bytes[start..]);
+        if line.is_empty() {
+            // Blank lines before the request line are ignored.
+            if req.is_empty() {
+                continue;
+            }
+            break;
+        }
+        let line = String::from_utf8(line.to_vec())
+            .or(Err(ParseError::BadRequest("Invalid characters in request")))?;
+        req.push(line);
+    }
+
+    // Process headers
+    let head = req
+        .first()
+        .ok_or(ParseError::BadRequest("Missing request line"))?
+        .split(' ')
+        .collect::<Vec<&str>>();
+    if head.len() != 3 || head[0].is_empty() || head[1].is_empty() {
+        return Err(ParseError::BadRequest("Malformed request line"));
+    }
+    if head[1].len() > limits.max_uri_length {
+        return Err(ParseError::UriTooLong);
+    }
+    if !head[2].starts_with("HTTP/") {
+        return Err(ParseError::BadRequest("Malformed request line"));
+    }
+    if head[2] != "HTTP/1.1" && head[2] != "HTTP/1.0" {
+        return Err(ParseError::UnsupportedVersion);
+    }
+
+    let (path, query) = head[1].split_once('?').unwrap_or((head[1], ""));
+
+    if req.len() - 1 > limits.max_header_count {
+        return Err(ParseError::HeadersTooLarge);
+    }
+    let mut headers = HeaderMap::new();
+    for line in &req[1..] {
+        let (key, value) = split_field(line, ParseError::BadRequest("Malformed header"))?;
+        headers.append(key, value);
+    }
+
+    let content_type = headers.get("Content-Type").and_then(MediaType::parse);
+    return Ok(Request {
+        path: percent_decode(path, false),
+        raw_path: path.to_string(),
+        query: QueryMap::from_string(query),
+        request_type: RequestType::type_for_method(head[0]),
+        version: head[2].to_string(),
+        headers,
+        trailers: HeaderMap::new(),
+        content_type,
+        params: HashMap::new(),
+        raw_body: None,
+        body: OnceCell::new(),
+        body_streamed: false,
+        multipart: MultipartOptions::new(),
+        close_connection: false,
+    });
+}
+
+// Split a header or trailer line into its name and value.
+fn split_field(line: &str, malformed: ParseError) -> Result<(&str, &str), ParseError> {
+    let Some((key, value)) = line.split_once(':') else {
+        return Err(malformed);
+    };
+    // Folded header lines and whitespace around names aren't allowed.
+    if key.is_empty() || key.starts_with([' ', '\t']) || key.ends_with([' ', '\t']) {
+        return Err(malformed);
+    }
+    Ok((key, value.trim()))
+}
+
+// Read the body, if the request has one. It is returned as sent,
+// decoding it is up to the caller. The request is only changed
+// when a chunked body carries trailers, or when its framing
+// means the connection has to close afterwards.
+pub fn read_request_body<R: BufRead>(
+    buffer: &mut R,
+    request: &mut Request,
+    limits: &RequestLimits,
+    max_body_bytes: usize,
+) -> Result<Option<Vec<u8>>, ParseError> {
+    let Some(mut reader) = body_reader(buffer, request, limits, max_body_bytes)? else {
+        return Ok(None);
+    };
+    // Memory grows as the body arrives rather than trusting the
+    // declared length up front.
+    let mut content: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut content)?;
+    return Ok(Some(content));
+}
+
+// A reader over the request's body, if it has one, which ends where
+// the body does. Bodies over `max_body_bytes` are refused.
+pub(crate) fn body_reader<'a, R: BufRead>(
+    buffer: &'a mut R,
+    request: &'a mut Request,
+    limits: &'a RequestLimits,
+    max_body_bytes: usize,
+) -> Result<Option<BodyReader<'a, R>>, ParseError> {
+    if is_chunked(request)? {
+        let header_count = request.headers.len();
+        let chunked = ChunkedReader::new(
+            buffer,
+            &mut request.trailers,
+            header_count,
+            limits,
+            max_body_bytes,
+        );
+        return Ok(Some(BodyReader::Chunked(chunked)));
+    }
+    let Some(content_len) = content_length(&request.headers)? else {
+        return Ok(None);
+    };
+    if content_len > max_body_bytes {
+        return Err(ParseError::PayloadTooLarge);
+    }
+    Ok(Some(BodyReader::Length(buffer.take(content_len as u64))))
+}
+
+// The declared body length. It must be plain digits, and repeats of it,
+// whether as separate headers or a list, have to agree, otherwise the
+// request could be read differently by a proxy in front of us.
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, ParseError> {
+    let mut content_len = None;
+    for value in headers.get_all("Content-Length") {
+        for value in value.split(',').map(|v| v.trim()) {
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::BadRequest("Invalid Content-Length"));
+            }
+            // Only digits are left, so this can only fail by overflowing.
+            let len: usize = value.parse().or(Err(ParseError::PayloadTooLarge))?;
+            if content_len.is_some_and(|previous| previous != len) {
+                return Err(ParseError::BadRequest("Conflicting Content-Length"));
+            }
+            content_len = Some(len);
+        }
+    }
+    Ok(content_len)
+}
+
+pub(crate) enum BodyReader<'a, R: BufRead> {
+    Chunked(ChunkedReader<'a, R>),
+    Length(Take<&'a mut R>),
+}
+impl<R: BufRead> Read for BodyReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BodyReader::Chunked(reader) => reader.read(buf),
+            BodyReader::Length(reader) => {
+                let read = reader.read(buf)?;
+                // The client hung up before sending all it said it would.
+                if read == 0 && reader.limit() > 0 && !buf.is_empty() {
+                    return Err(ParseError::ConnectionClosed.into());
+                }
+                Ok(read)
+            }
+        }
+    }
+}
+
+// Whether the body is chunked. Chunked has to be the final transfer
+// coding, anything else leaves no way to tell where the body ends.
+// No other codings are supported.
+pub(crate) fn is_chunked(request: &mut Request) -> Result<bool, ParseError> {
+    let transfer_encoding = request.headers.get_all("Transfer-Encoding").join(",");
+    let codings = transfer_encoding
+        .split(',')
+        .map(|c| c.trim().to_ascii_lowercase())
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<String>>();
+    if codings.is_empty() {
+        return Ok(false);
+    }
+    if codings.last().map(|c| c.as_str()) != Some("chunked") {
+        return Err(ParseError::BadRequest(
+            "Transfer-Encoding must end in chunked",
+        ));
+    }
+    if codings.len() > 1 {
+        return Err(ParseError::NotImplemented);
+    }
+    // Chunked encoding wins over any Content-Length that was also sent,
+    // but a peer that sent both may disagree about where this request
+    // ends, so nothing more is read from the connection.
+    if request.headers.contains_key("Content-Length") {
+        request.close_connection = true;
+    }
+    Ok(true)
+}
+
+// Longest chunk size line accepted, extensions included.
+const MAX_CHUNK_LINE: usize = 4096;
+
+// Decodes a chunked body as it is read. Each chunk is a hex size line
+// followed by that many bytes and a CRLF. A zero sized chunk ends the
+// body, and may be followed by trailer fields. Trailers count against
+// the same limits as the request's headers.
+pub(crate) struct ChunkedReader<'a, R: BufRead> {
+    buffer: &'a mut R,
+    trailers: &'a mut HeaderMap,
+    // Headers the request already has, towards the header count limit.
+    header_count: usize,
+    limits: &'a RequestLimits,
+    max_body_bytes: usize,
+    read: usize,
+    // Bytes left in the current chunk.
+    remaining: usize,
+    done: bool,
+}
+impl<'a, R: BufRead> ChunkedReader<'a, R> {
+    pub(crate) fn new(
+        buffer: &'a mut R,
+        trailers: &'a mut HeaderMap,
+        header_count: usize,
+        limits: &'a RequestLimits,
+        max_body_bytes: usize,
+    ) -> ChunkedReader<'a, R> {
+        ChunkedReader {
+            buffer,
+            trailers,
+            header_count,
+            limits,
+            max_body_bytes,
+            read: 0,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<usize, ParseError> {
+        let too_long = ParseError::BadRequest("Chunk size line too long");
+        let size_line = read_line(self.buffer, MAX_CHUNK_LINE, too_long)?;
+        // Chunk extensions (";name=value") are ignored.
+        let size_str = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_str, 16)
+            .or(Err(ParseError::BadRequest("Invalid chunk size")))?;
+        if size > self.max_body_bytes - self.read {
+            return Err(ParseError::PayloadTooLarge);
+        }
+        Ok(size)
+    }
+
+    fn read_trailers(&mut self) -> Result<(), ParseError> {
+        let mut allowed = self.limits.max_header_bytes;
+        loop {
+            let trailer = read_line(self.buffer, allowed, ParseError::HeadersTooLarge)?;
+            if trailer.is_empty() {
+                return Ok(());
+            }
+            allowed = allowed.saturating_sub(trailer.len() + 2);
+            if self.header_count + self.trailers.len() >= self.limits.max_header_count {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            let (key, value) = split_field(&trailer, ParseError::BadRequest("Malformed trailer"))?;
+            self.trailers.append(key, value);
+        }
+    }
+}
+impl<R: BufRead> Read for ChunkedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.remaining = self.next_chunk()?;
+            if self.remaining == 0 {
+                self.read_trailers()?;
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let max = buf.len().min(self.remaining);
+        let read = self.buffer.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(ParseError::ConnectionClosed.into());
+        }
+        self.remaining -= read;
+        self.read += read;
+        if self.remaining == 0 {
+            let too_long = ParseError::BadRequest("Malformed chunk");
+            if !read_line(self.buffer, 2, too_long)?.is_empty() {
+                return Err(ParseError::BadRequest("Malformed chunk").into());
+            }
+        }
+        Ok(read)
+    }
+}
+
+// Read a single line of at most `max_len` bytes, line ending included,
+// and return it without its line ending.
+fn read_line<R: BufRead>(
+    buffer: &mut R,
+    max_len: usize,
+    too_long: ParseError,
+) -> Result<String, ParseError> {
+    let mut line: Vec<u8> = Vec::new();
+    buffer
+        .by_ref()
+        .take(max_len as u64)
+        .read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        if line.len() == max_len {
+            return Err(too_long);
+        }
+        return Err(ParseError::ConnectionClosed);
+    }
+    let line = trim_line_ending(&line).to_vec();
+    String::from_utf8(line).or(Err(ParseError::BadRequest("Invalid characters in request")))
+}
+
+// Strip a trailing LF or CRLF.
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    ConnectionClosed,
+    Timeout,
+    BadRequest(&'static str),
+    UriTooLong,
+    HeadersTooLarge,
+    PayloadTooLarge,
+    NotImplemented,
+    UnsupportedVersion,
+}
+impl ParseError {
+    // The status to answer with. A closed connection gets no answer.
+    pub fn status(&self) -> Option<ResponseStatusCode> {
+        match self {
+            ParseError::ConnectionClosed => None,
+            ParseError::Timeout => Some(ResponseStatusCode::RequestTimeout),
+            ParseError::BadRequest(_) => Some(ResponseStatusCode::BadRequest),
+            ParseError::UriTooLong => Some(ResponseStatusCode::UriTooLong),
+            ParseError::HeadersTooLarge => Some(ResponseStatusCode::RequestHeaderFieldsTooLarge),
+            ParseError::PayloadTooLarge => Some(ResponseStatusCode::PayloadTooLarge),
+            ParseError::NotImplemented => Some(ResponseStatusCode::NotImplemented),
+            ParseError::UnsupportedVersion => Some(ResponseStatusCode::HttpVersionNotSupported),
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ParseError::ConnectionClosed => "Connection closed",
+            ParseError::Timeout => "Request timed out",
+            ParseError::BadRequest(message) => message,
+            ParseError::UriTooLong => "URI too long",
+            ParseError::HeadersTooLarge => "Request headers too large",
+            ParseError::PayloadTooLarge => "Request body too large",
+            ParseError::NotImplemented => "Transfer coding not supported",
+            ParseError::UnsupportedVersion => "HTTP version not supported",
+        }
+    }
+
+    // The connection can't be trusted after a bad request,
+    // so the response always closes it.
+    pub fn response(&self) -> Option<Response> {
+        let status_code = self.status()?;
+        let error = RouteError {
+            message: self.message().to_string(),
+            status_code,
+            override_output: false,
+        };
+        Some(error.response().header("Connection", "close"))
+    }
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+impl std::error::Error for ParseError {}
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> ParseError {
+        match error.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ParseError::Timeout,
+            // Errors found while decoding a body pass through io::Read.
+            io::ErrorKind::Other => match error.into_inner().map(|e| e.downcast::<ParseError>()) {
+                Some(Ok(error)) => *error,
+                _ => ParseError::ConnectionClosed,
+            },
+            _ => ParseError::ConnectionClosed,
+        }
+    }
+}
+impl From<ParseError> for io::Error {
+    fn from(error: ParseError) -> io::Error {
+        io::Error::other(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::super::{Request, RequestLimits};
+    use super::{read_request_body, read_request_head, ParseError};
+
+    fn parse(
+        input: &str,
+        limits: &RequestLimits,
+    ) -> Result<(Request, Option<Vec<u8>>), ParseError> {
+        let mut reader = BufReader::new(input.as_bytes());
+        let mut request = read_request_head(&mut reader, limits)?;
+        let body = read_request_body(&mut reader, &mut request, limits, limits.max_body_bytes)?;
+        Ok((request, body))
+    }
+
+    fn chunked(body: &str) -> String {
+        format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+            body
+        )
+    }
+
+    #[test]
+    fn decodes_chunks_extensions_and_trailers() {
+        let input =
+            chunked("5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: abc\r\n\r\n");
+        let (request, body) = parse(&input, &RequestLimits::new()).unwrap();
+        assert_eq!(body.unwrap(), b"hello, world");
+        assert_eq!(request.trailers.get("X-Checksum"), Some("abc"));
+        assert_eq!(request.headers.get("X-Checksum"), None);
+        assert!(request.keep_alive());
+    }
+
+    #[test]
+    fn keeps_trailers_out_of_the_headers() {
+        let input = chunked("3\r\nabc\r\n0\r\nContent-Length: 99\r\nAuthorization: x\r\n\r\n");
+        let (request, body) = parse(&input, &RequestLimits::new()).unwrap();
+        assert_eq!(body.unwrap(), b"abc");
+        assert_eq!(request.headers.get("Content-Length"), None);
+        assert_eq!(request.headers.get("Authorization"), None);
+        assert_eq!(request.trailers.get("Content-Length"), Some("99"));
+        assert!(request.keep_alive());
+    }
+
+    #[test]
+    fn bounds_the_uri_length() {
+        let mut limits = RequestLimits::new();
+        limits.max_uri_length = 10;
+        assert!(parse("GET /123456789 HTTP/1.1\r\n\r\n", &limits).is_ok());
+        assert!(matches!(
+            parse("GET /1234567890 HTTP/1.1\r\n\r\n", &limits),
+            Err(ParseError::UriTooLong)
+        ));
+        // A request line over the header byte limit is blamed on its URI.
+        let mut limits = RequestLimits::new();
+        limits.max_header_bytes = 20;
+        assert!(matches!(
+            parse(
+                &format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(30)),
+                &limits
+            ),
+            Err(ParseError::UriTooLong)
+        ));
+    }
+
+    #[test]
+    fn bounds_the_header_size() {
+        let mut limits = RequestLimits::new();
+        limits.max_header_bytes = 50;
+        let request = |value: &str| format!("GET / HTTP/1.1\r\nX-Value: {}\r\n\r\n", value);
+        assert!(parse(&request("short"), &limits).is_ok());
+        assert!(matches!(
+            parse(&request(&"x".repeat(40)), &limits),
+            Err(ParseError::HeadersTooLarge)
+        ));
+
+        let mut limits = RequestLimits::new();
+        limits.max_header_count = 2;
+        let request = |count: usize| {
+            let headers: String = (0..count).map(|i| format!("X-{}: {}\r\n", i, i)).collect();
+            format!("GET / HTTP/1.1\r\n{}\r\n", headers)
+        };
+        assert!(parse(&request(2), &limits).is_ok());
+        assert!(matches!(
+            parse(&request(3), &limits),
+            Err(ParseError::HeadersTooLarge)
+        ));
+    }
+
+    #[test]
+    fn accepts_bare_line_feeds() {
+        let input = chunked("3\nabc\n0\n\n");
+        let (_, body) = parse(&input, &RequestLimits::new()).unwrap();
+        assert_eq!(body.unwrap(), b"abc");
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let limits = RequestLimits::new();
+        assert!(matches!(
+            parse(&chunked("zz\r\nabc\r\n0\r\n\r\n"), &limits),
+            Err(ParseError::BadRequest("Invalid chunk size"))
+        ));
+        assert!(matches!(
+            parse(&chunked("3\r\nabcdef\r\n0\r\n\r\n"), &limits),
+            Err(ParseError::BadRequest("Malformed chunk"))
+        ));
+        assert!(matches!(
+            parse(&chunked("3\r\nab"), &limits),
+            Err(ParseError::ConnectionClosed)
+        ));
+        for trailer in ["No colon", ": empty name", "X-Spaced : 1", " X-Folded: 1"] {
+            let input = chunked(&format!("0\r\n{}\r\n\r\n", trailer));
+            assert!(matches!(
+                parse(&input, &limits),
+                Err(ParseError::BadRequest("Malformed trailer"))
+            ));
+        }
+    }
+
+    #[test]
+    fn bounds_chunk_size_lines() {
+        let extension = format!("1;{}\r\na\r\n0\r\n\r\n", "x".repeat(5000));
+        assert!(matches!(
+            parse(&chunked(&extension), &RequestLimits::new()),
+            Err(ParseError::BadRequest("Chunk size line too long"))
+        ));
+        let endless = format!("1;{}", "x".repeat(5000));
+        assert!(matches!(
+            parse(&chunked(&endless), &RequestLimits::new()),
+            Err(ParseError::BadRequest("Chunk size line too long"))
+        ));
+    }
+
+    #[test]
+    fn bounds_body_size() {
+        let mut limits = RequestLimits::new();
+        limits.max_body_bytes = 5;
+        assert!(parse(&chunked("3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n"), &limits).is_ok());
+        assert!(matches!(
+            parse(&chunked("3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n"), &limits),
+            Err(ParseError::PayloadTooLarge)
+        ));
+        assert!(matches!(
+            parse(&chunked("ffffffffffffffff\r\n"), &limits),
+            Err(ParseError::PayloadTooLarge)
+        ));
+    }
+
+    #[test]
+    fn counts_trailers_against_header_limits() {
+        let mut limits = RequestLimits::new();
+        limits.max_header_bytes = 200;
+        let long_trailer = format!("0\r\nX-Long: {}\r\n\r\n", "x".repeat(300));
+        assert!(matches!(
+            parse(&chunked(&long_trailer), &limits),
+            Err(ParseError::HeadersTooLarge)
+        ));
+
+        let mut limits = RequestLimits::new();
+        limits.max_header_count = 2;
+        assert!(parse(&chunked("0\r\nX-One: 1\r\n\r\n"), &limits).is_ok());
+        assert!(matches!(
+            parse(&chunked("0\r\nX-One: 1\r\nX-Two: 2\r\n\r\n"), &limits),
+            Err(ParseError::HeadersTooLarge)
+        ));
+    }
+
+    #[test]
+    fn requires_chunked_as_the_final_coding() {
+        let limits = RequestLimits::new();
+        let request = |te: &str| {
+            format!(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n0\r\n\r\n",
+                te
+            )
+        };
+        assert!(matches!(
+            parse(&request("gzip"), &limits),
+            Err(ParseError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse(&request("chunked, gzip"), &limits),
+            Err(ParseError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse(&request("gzip, chunked"), &limits),
+            Err(ParseError::NotImplemented)
+        ));
+        assert!(parse(&request("Chunked"), &limits).is_ok());
+    }
+
+    #[test]
+    fn requires_a_plain_content_length() {
+        let limits = RequestLimits::new();
+        let request = |lengths: &str| format!("POST / HTTP/1.1\r\n{}\r\nabc", lengths);
+        let (_, body) = parse(&request("Content-Length: 3\r\n"), &limits).unwrap();
+        assert_eq!(body.unwrap(), b"abc");
+        for length in ["+3", "-3", "3 3", "0x3", ""] {
+            let input = request(&format!("Content-Length: {}\r\n", length));
+            assert!(matches!(
+                parse(&input, &limits),
+                Err(ParseError::BadRequest("Invalid Content-Length"))
+            ));
+        }
+        assert!(matches!(
+            parse(
+                &request("Content-Length: 99999999999999999999999\r\n"),
+                &limits
+            ),
+            Err(ParseError::PayloadTooLarge)
+        ));
+    }
+
+    #[test]
+    fn requires_repeated_content_lengths_to_agree() {
+        let limits = RequestLimits::new();
+        let request = |lengths: &str| format!("POST / HTTP/1.1\r\n{}\r\nabc", lengths);
+        let same = request("Content-Length: 3\r\nContent-Length: 3, 3\r\n");
+        assert_eq!(parse(&same, &limits).unwrap().1.unwrap(), b"abc");
+        for lengths in [
+            "Content-Length: 3\r\nContent-Length: 2\r\n",
+            "Content-Length: 3, 2\r\n",
+        ] {
+            assert!(matches!(
+                parse(&request(lengths), &limits),
+                Err(ParseError::BadRequest("Conflicting Content-Length"))
+            ));
+        }
+    }
+
+    #[test]
+    fn closes_after_both_transfer_encoding_and_content_length() {
+        let input = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n\
+                     3\r\nabc\r\n0\r\n\r\n";
+        let (request, body) = parse(input, &RequestLimits::new()).unwrap();
+        assert_eq!(body.unwrap(), b"abc");
+        assert!(!request.keep_alive());
+    }
+}
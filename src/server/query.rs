@@ -0,0 +1,139 @@
+use super::RouteError;
+
+#[derive(Debug)]
+pub struct QueryMap {
+    values: Vec<(String, String)>,
+}
+
+impl QueryMap {
+    pub fn new() -> QueryMap {
+        QueryMap { values: Vec::new() }
+    }
+
+    pub fn from_string(query: &str) -> QueryMap {
+        let values = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key, true), percent_decode(value, true))
+            })
+            .collect();
+        QueryMap { values }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.values
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn all(&self) -> &Vec<(String, String)> {
+        return &self.values;
+    }
+
+    pub fn string(&self, key: &str) -> Option<String> {
+        self.get(key).map(|v| v.to_string())
+    }
+
+    pub fn validate_string(&self, key: &str, err: &str) -> Result<String, RouteError> {
+        self.string(key).ok_or(RouteError::bad_request(err))
+    }
+
+    pub fn i32(&self, key: &str) -> Option<i32> {
+        self.get(key)?.parse().ok()
+    }
+
+    pub fn validate_i32(&self, key: &str, err: &str) -> Result<i32, RouteError> {
+        let value = self.get(key).ok_or(RouteError::bad_request(err))?;
+        value.parse().or(Err(RouteError::bad_request(err)))
+    }
+
+    pub fn i64(&self, key: &str) -> Option<i64> {
+        self.get(key)?.parse().ok()
+    }
+
+    pub fn validate_i64(&self, key: &str, err: &str) -> Result<i64, RouteError> {
+        let value = self.get(key).ok_or(RouteError::bad_request(err))?;
+        value.parse().or(Err(RouteError::bad_request(err)))
+    }
+
+    pub fn f32(&self, key: &str) -> Option<f32> {
+        self.get(key)?.parse().ok()
+    }
+
+    pub fn validate_f32(&self, key: &str, err: &str) -> Result<f32, RouteError> {
+        let value = self.get(key).ok_or(RouteError::bad_request(err))?;
+        value.parse().or(Err(RouteError::bad_request(err)))
+    }
+
+    pub fn f64(&self, key: &str) -> Option<f64> {
+        self.get(key)?.parse().ok()
+    }
+
+    pub fn validate_f64(&self, key: &str, err: &str) -> Result<f64, RouteError> {
+        let value = self.get(key).ok_or(RouteError::bad_request(err))?;
+        value.parse().or(Err(RouteError::bad_request(err)))
+    }
+
+    pub fn bool(&self, key: &str) -> Option<bool> {
+        self.get(key)?.parse().ok()
+    }
+
+    pub fn validate_bool(&self, key: &str, err: &str) -> Result<bool, RouteError> {
+        let value = self.get(key).ok_or(RouteError::bad_request(err))?;
+        value.parse().or(Err(RouteError::bad_request(err)))
+    }
+}
+impl Default for QueryMap {
+    fn default() -> QueryMap {
+        QueryMap::new()
+    }
+}
+
+// Decode %XX escapes. Query strings also use '+' for spaces,
+// paths do not. Invalid escapes are kept as-is.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut output: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut ix = 0;
+    while ix < bytes.len() {
+        match bytes[ix] {
+            b'%' if ix + 2 < bytes.len() => {
+                match (hex_value(bytes[ix + 1]), hex_value(bytes[ix + 2])) {
+                    (Some(high), Some(low)) => {
+                        output.push(high * 16 + low);
+                        ix += 2;
+                    }
+                    _ => output.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => output.push(b' '),
+            b => output.push(b),
+        }
+        ix += 1;
+    }
+    return String::from_utf8_lossy(&output).into_owned();
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
@@ -1,24 +1,45 @@
-use super::IncomingRequest;
+use super::{
+    parser::ParseError, reqres::Framing, router::RouteStorage, server::next_request, ChunkedWriter,
+    Connection, IncomingRequest, RequestType, ResponseStatusCode, RouteError, ServerConfig,
+    TimedStream,
+};
 use std::{
-    io::Write,
+    io::{self, BufRead, BufReader, Read, Write},
     sync::{
+        atomic::{AtomicUsize, Ordering},
         mpsc::{channel, Receiver, Sender},
         Arc, Mutex,
     },
-    thread::{available_parallelism, spawn, JoinHandle},
+    thread::{self, available_parallelism, spawn, JoinHandle},
 };
 
 pub struct RequestQueue<T: 'static + Send> {
     workers: Vec<RequestWorker>,
     sender: Option<Sender<IncomingRequest<T>>>,
+    routes: Arc<RouteStorage<T>>,
+    config: Arc<ServerConfig>,
+    // Connections with a thread waiting on them.
+    connections: Arc<AtomicUsize>,
 }
 impl<T: 'static + Send> RequestQueue<T> {
-    pub fn new(f: WorkerSetupFn<T>) -> RequestQueue<T> {
-        let wc = available_parallelism().unwrap().get() / 2;
-        RequestQueue::new_with_thread_count(f, wc)
+    pub fn new(
+        f: WorkerSetupFn<T>,
+        routes: Arc<RouteStorage<T>>,
+        config: Arc<ServerConfig>,
+    ) -> RequestQueue<T> {
+        // Always start at least one worker, even on a single core.
+        let wc = available_parallelism().map_or(1, |n| n.get() / 2).max(1);
+        RequestQueue::new_with_thread_count(f, routes, config, wc)
     }
 
-    pub fn new_with_thread_count(f: WorkerSetupFn<T>, thread_count: usize) -> RequestQueue<T> {
+    // Every connection shares the same routes and config. They are only
+    // read once the server has started, so no locking is needed.
+    pub fn new_with_thread_count(
+        f: WorkerSetupFn<T>,
+        routes: Arc<RouteStorage<T>>,
+        config: Arc<ServerConfig>,
+        thread_count: usize,
+    ) -> RequestQueue<T> {
         let (sender, reciever) = channel::<IncomingRequest<T>>();
         let mut workers: Vec<RequestWorker> = Vec::new();
         let rc_mutex = Arc::new(Mutex::new(reciever));
@@ -26,18 +47,113 @@ impl<T: 'static + Send> RequestQueue<T> {
         println!("Started with {} threads", &thread_count);
 
         for _ in 0..thread_count {
-            workers.push(RequestWorker::spawn(rc_mutex.clone(), f));
+            workers.push(RequestWorker::spawn(rc_mutex.clone(), f, config.clone()));
         }
 
         RequestQueue {
             workers,
             sender: Some(sender),
+            routes,
+            config,
+            connections: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     pub fn add(&self, ir: IncomingRequest<T>) {
         _ = &self.sender.as_ref().unwrap().send(ir);
     }
+
+    // Give a new connection a thread of its own to read requests on.
+    // Workers are only handed a request once it has arrived whole, body
+    // included, so idle or slow clients never hold one up.
+    pub fn connect(&self, mut stream: Box<dyn Connection>) -> io::Result<()> {
+        // Reserve a slot before starting the thread, so connections
+        // accepted at the same time can't both take the last one.
+        let max = self.config.max_connections;
+        let reserved = self
+            .connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            });
+        if reserved.is_err() {
+            let response = RouteError {
+                message: "Too many connections".to_string(),
+                status_code: ResponseStatusCode::ServiceUnavailable,
+                override_output: false,
+            }
+            .response()
+            .header("Connection", "close");
+            let mut output = response.get_header_data();
+            output.append(&mut response.bytes());
+            _ = stream.set_write_timeout(Some(self.config.write_timeout));
+            return stream.write_all(&output);
+        }
+        let sender = self.sender.clone().unwrap();
+        let routes = self.routes.clone();
+        let config = self.config.clone();
+        let connections = self.connections.clone();
+        let watcher = thread::Builder::new()
+            // Reading requests needs far less stack than the default.
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                RequestQueue::watch(stream, sender, &routes, &config);
+                connections.fetch_sub(1, Ordering::AcqRel);
+            });
+        if watcher.is_err() {
+            self.connections.fetch_sub(1, Ordering::AcqRel);
+        }
+        watcher.map(|_| ())
+    }
+
+    fn watch(
+        stream: Box<dyn Connection>,
+        sender: Sender<IncomingRequest<T>>,
+        routes: &RouteStorage<T>,
+        config: &ServerConfig,
+    ) {
+        let stream = TimedStream::new(stream);
+        _ = stream.set_write_timeout(config.write_timeout);
+        // The reader lives as long as the connection so bytes of any
+        // pipelined requests it has already buffered are not lost.
+        let mut reader = BufReader::new(stream);
+        let mut served = 0;
+        loop {
+            let next = match wait_for_head(&mut reader, config, served == 0) {
+                Ok(true) => next_request(&mut reader, routes, config),
+                Ok(false) => return,
+                Err(error) => Err(error),
+            };
+            let (request, route) = match next {
+                Ok(next) => next,
+                Err(error) => {
+                    if let Some(response) = error.response() {
+                        let mut output = response.get_header_data();
+                        output.append(&mut response.bytes());
+                        _ = reader.get_mut().write_all(&output);
+                    }
+                    return;
+                }
+            };
+            served += 1;
+            // The worker hands the connection back once it has answered,
+            // or drops it to close it.
+            let (idle, returned) = channel();
+            let ir = IncomingRequest {
+                stream: reader,
+                request,
+                route,
+                served,
+                idle,
+            };
+            if sender.send(ir).is_err() {
+                return;
+            }
+            let Ok(stream) = returned.recv() else {
+                return;
+            };
+            reader = stream;
+        }
+    }
 }
 impl<T: 'static + Send> Drop for RequestQueue<T> {
     fn drop(&mut self) {
@@ -57,27 +173,15 @@ impl RequestWorker {
     pub fn spawn<T: 'static + Send>(
         reciever: Arc<Mutex<Receiver<IncomingRequest<T>>>>,
         setup_fn: WorkerSetupFn<T>,
+        config: Arc<ServerConfig>,
     ) -> RequestWorker {
         let thread = spawn(move || {
         	// Spawn the required helper object
             let data = setup_fn();
             loop {
                 let ir_task_op = reciever.lock().unwrap().recv();
-                if let Ok(mut ir_task) = ir_task_op {
-                    // Tell the handler to parse it
-                    let mut bytes = Vec::new();
-                    match (ir_task.route)(ir_task.request, &data) {
-                        Ok(body) => {
-                            bytes.append(&mut body.get_header_data());
-                            bytes.append(&mut body.bytes())
-                        },
-                        Err(error) => {
-                            bytes.append(&mut error.header());
-                            bytes.append(&mut error.output().into_bytes())
-                        }
-                    };
-                    // Write stream
-                    _ = ir_task.stream.write(&bytes);
+                if let Ok(ir_task) = ir_task_op {
+                    RequestWorker::serve(ir_task, &data, &config);
                 } else {
                     break;
                 }
@@ -88,6 +192,158 @@ impl RequestWorker {
             thread: Some(thread),
         }
     }
+
+    // Answer a request that has been read in full. A connection that
+    // stays open is handed back to wait for its next request, so
+    // pipelined responses still go out in the order they arrived.
+    fn serve<T: 'static + Send>(ir_task: IncomingRequest<T>, data: &T, config: &ServerConfig) {
+        let IncomingRequest {
+            stream: mut reader,
+            request,
+            route,
+            served,
+            idle,
+        } = ir_task;
+        let mut keep_alive = config.keep_alive
+            && request.keep_alive()
+            && served < config.max_requests_per_connection;
+
+        // Tell the handler to parse it
+        let head_only = request.request_type == RequestType::Head;
+        let http_1_0 = request.version == "HTTP/1.0";
+        let mut response = match route.call(request, data) {
+            Ok(response) => response,
+            Err(error) => error.response(),
+        };
+        response = response.for_request(head_only, http_1_0);
+        if response.framing() == Framing::Close {
+            keep_alive = false;
+        }
+        response = response.header("Connection", if keep_alive { "keep-alive" } else { "close" });
+        let mut output = response.get_header_data();
+        let written = match response.take_stream() {
+            Some(stream) => reader.get_mut().write_all(&output).and_then(|_| {
+                let mut writer = if response.framing() == Framing::Close {
+                    ChunkedWriter::raw(reader.get_mut())
+                } else {
+                    ChunkedWriter::new(reader.get_mut())
+                };
+                // A failed stream can't be reported to the client any more,
+                // the connection is closed instead so it sees the body end early.
+                stream(&mut writer).and_then(|_| writer.finish())
+            }),
+            None => {
+                output.append(&mut response.bytes());
+                reader.get_mut().write_all(&output)
+            }
+        };
+        if keep_alive && written.is_ok() {
+            _ = idle.send(reader);
+        }
+    }
+}
+
+// Read until the client has sent a whole request head, so a worker can
+// parse it without waiting on the client. A head over the size limit
+// is handed on as soon as that is clear, for the worker to reject.
+// Returns false if the client closed the connection, or idled past the
+// keep-alive timeout, before starting another request.
+fn wait_for_head(
+    reader: &mut BufReader<TimedStream>,
+    config: &ServerConfig,
+    first: bool,
+) -> Result<bool, ParseError> {
+    // Bytes of a pipelined request may already be in the reader's buffer.
+    let buffered = reader.buffer().to_vec();
+    reader.consume(buffered.len());
+    let stream = reader.get_mut();
+    stream.unread(buffered);
+    let mut head = stream.take_unread();
+    // The first request has to arrive within the header timeout of
+    // connecting. Later ones may idle for the keep-alive timeout first.
+    if first || !head.is_empty() {
+        stream.set_deadline(config.header_read_timeout);
+    } else {
+        stream.set_deadline(config.keep_alive_timeout);
+    }
+    let mut chunk = [0; 4096];
+    let mut scanner = HeadScanner::new();
+    let mut scanned = 0;
+    loop {
+        let complete = scanner.scan(&head[scanned..]);
+        scanned = head.len();
+        if complete || head.len() > config.limits.max_header_bytes {
+            stream.unread(head);
+            return Ok(true);
+        }
+        let read = match stream.read(&mut chunk) {
+            Ok(read) => read,
+            // An idle kept-alive connection is closed quietly once the
+            // client stops sending, rather than answered with a 408.
+            Err(_) if head.is_empty() && !first => return Ok(false),
+            Err(error) => return Err(error.into()),
+        };
+        if read == 0 {
+            if head.is_empty() {
+                return Ok(false);
+            }
+            return Err(ParseError::ConnectionClosed);
+        }
+        if head.is_empty() && !first {
+            stream.set_deadline(config.header_read_timeout);
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+}
+
+// Finds the end of a request head as its bytes come in. Blank lines
+// before the request line are skipped, after it the head ends at the
+// first empty line.
+struct HeadScanner {
+    started: bool,
+    line_feeds: usize,
+}
+impl HeadScanner {
+    fn new() -> HeadScanner {
+        HeadScanner {
+            started: false,
+            line_feeds: 0,
+        }
+    }
+
+    // True once the bytes scanned so far hold a whole head.
+    fn scan(&mut self, bytes: &[u8]) -> bool {
+        for byte in bytes {
+            match byte {
+                b'\n' if self.started => self.line_feeds += 1,
+                b'\r' | b'\n' => {}
+                _ => {
+                    self.started = true;
+                    self.line_feeds = 0;
+                }
+            }
+            if self.line_feeds == 2 {
+                return true;
+            }
+        }
+        false
+    }
 }
 
 pub type WorkerSetupFn<T> = fn() -> T;
+
+#[cfg(test)]
+mod tests {
+    use super::HeadScanner;
+
+    #[test]
+    fn head_ends_at_first_empty_line() {
+        let mut scanner = HeadScanner::new();
+        assert!(!scanner.scan(b"\r\n\r\nGET / HTTP/1.1\r\nHost: x\r\n"));
+        assert!(!scanner.scan(b"\r"));
+        assert!(scanner.scan(b"\nbody"));
+        assert!(HeadScanner::new().scan(b"GET / HTTP/1.1\n\n"));
+        assert!(!HeadScanner::new().scan(b"GET / HTTP/1.1\r\nHost: x\r\n"));
+        assert!(!HeadScanner::new().scan(b"\r\n\r\n"));
+    }
+}
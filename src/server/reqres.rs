@@ -1,33 +1,148 @@
-use super::{JsonArray, JsonObject, ToJson};
-use std::collections::HashMap;
+use super::{
+    date::http_date, json::check_syntax, media_type::decode_text, ChunkedWriter, HeaderMap,
+    JsonArray, JsonObject, MediaType, Multipart, MultipartOptions, QueryMap, ResponseStream,
+    ToJson,
+};
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    io::{self, Write},
+    time::SystemTime,
+};
 
 #[derive(Debug)]
 pub struct Request {
     pub request_type: RequestType,
+    // The path with percent-escapes decoded, for display. Routes are
+    // matched against `raw_path` so an escaped "/" stays in its segment.
     pub path: String,
-    pub headers: HashMap<String, String>,
-    pub body: BodyContents,
+    pub raw_path: String,
+    pub query: QueryMap,
+    pub version: String,
+    pub headers: HeaderMap,
+    // Trailer fields sent after a chunked body. They are kept apart from
+    // the headers so a client can't use them to change how the request
+    // was framed, routed or authorized.
+    pub trailers: HeaderMap,
+    // The parsed Content-Type header, if one was sent.
+    pub content_type: Option<MediaType>,
+    pub params: HashMap<String, String>,
+    // The body as sent. It is only decoded once a handler asks for it.
+    pub(crate) raw_body: Option<Vec<u8>>,
+    pub(crate) body: OnceCell<Result<BodyContents, RouteError>>,
+    // Set when the body was decoded as it arrived and not kept as sent.
+    pub(crate) body_streamed: bool,
+    pub(crate) multipart: MultipartOptions,
+    // Set when the request's framing can't be trusted to leave the
+    // connection in a known state for another request.
+    pub(crate) close_connection: bool,
+}
+impl Request {
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params.get(key).map(|p| p.as_str())
+    }
+
+    pub fn validate_param(&self, key: &str, err: &str) -> Result<String, RouteError> {
+        self.params.get(key).cloned().ok_or(RouteError::bad_request(err))
+    }
+
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.query.get(key)
+    }
+
+    // The body exactly as it was sent, e.g. to check a webhook's signature.
+    // None for multipart bodies when parts may be spilled to disk, as
+    // those are parsed while they are read instead of being kept.
+    pub fn raw_body(&self) -> Option<&[u8]> {
+        if self.body_streamed {
+            return None;
+        }
+        Some(self.raw_body.as_deref().unwrap_or(&[]))
+    }
+
+    // The body decoded according to its Content-Type. It is decoded the
+    // first time it is asked for and the result kept for later calls.
+    // A malformed body is a bad request, so handlers can just use `?`.
+    pub fn body(&self) -> Result<&BodyContents, RouteError> {
+        self.body
+            .get_or_init(|| match &self.raw_body {
+                Some(data) => {
+                    BodyContents::from_media_type(self.content_type.as_ref(), data, &self.multipart)
+                }
+                None => Ok(BodyContents::None),
+            })
+            .as_ref()
+            .map_err(|error| error.clone())
+    }
+
+    // Take the decoded body. One that was already decoded is moved
+    // out rather than copied.
+    pub fn into_body(mut self) -> Result<BodyContents, RouteError> {
+        if let Some(body) = self.body.take() {
+            return body;
+        }
+        match self.raw_body.take() {
+            Some(data) => {
+                BodyContents::from_media_type(self.content_type.as_ref(), &data, &self.multipart)
+            }
+            None => Ok(BodyContents::None),
+        }
+    }
+
+    pub fn query_all(&self, key: &str) -> Vec<&str> {
+        self.query.get_all(key)
+    }
+
+    // HTTP/1.1 connections stay open unless the client asks otherwise,
+    // HTTP/1.0 connections only when the client asks for it.
+    pub fn keep_alive(&self) -> bool {
+        if self.close_connection {
+            return false;
+        }
+        let connection = self
+            .headers
+            .get_all("Connection")
+            .join(",")
+            .to_ascii_lowercase();
+        let has_token = |token: &str| connection.split(',').any(|t| t.trim() == token);
+        if self.version == "HTTP/1.0" {
+            has_token("keep-alive")
+        } else {
+            !has_token("close")
+        }
+    }
 }
 
 pub struct Response {
     data: Vec<u8>,
+    stream: Option<ResponseStream>,
+    // Set for streamed bodies, even once a HEAD response has dropped the stream.
+    streamed: bool,
     status: ResponseStatusCode,
-    headers: HashMap<String, String>,
+    headers: HeaderMap,
+    // The body ends when the connection closes instead of being framed.
+    delimited_by_close: bool,
+    // The length of the body a HEAD response leaves out.
+    omitted_length: Option<usize>,
 }
 impl Response {
     pub fn new() -> Response {
         Response {
             data: Vec::new(),
+            stream: None,
+            streamed: false,
             status: ResponseStatusCode::Ok,
-            headers: HashMap::new(),
+            headers: HeaderMap::new(),
+            delimited_by_close: false,
+            omitted_length: None,
         }
     }
 
     pub fn data(data: Vec<u8>) -> Response {
         Response {
             data,
-            status: ResponseStatusCode::Ok,
-            headers: HashMap::new(),
+            ..Response::new()
         }
     }
     pub fn string<S: AsRef<str>>(s: S) -> Response {
@@ -40,20 +155,60 @@ impl Response {
         Response::data(s).header("Content-Type", "text/html")
     }
 
+    // The body is produced while it is being sent and goes out with
+    // chunked encoding, so it never has to be held in memory at once.
+    pub fn stream<F: FnOnce(&mut ChunkedWriter) -> io::Result<()> + 'static>(f: F) -> Response {
+        let mut response = Response::new();
+        response.stream = Some(Box::new(f));
+        response.streamed = true;
+        response
+    }
+    pub fn chunks<I: IntoIterator<Item = Vec<u8>> + 'static>(chunks: I) -> Response {
+        Response::stream(move |writer| {
+            for chunk in chunks {
+                writer.write_all(&chunk)?;
+            }
+            Ok(())
+        })
+    }
+
     pub fn get_header_data(&self) -> Vec<u8> {
         let mut output = String::from("HTTP/1.1 ");
         output += &self.status.http_string();
-        if self.headers.len() > 0 {
+        output += "\r\n";
+        for (key, value) in self.headers.iter() {
+            // The body is framed as `framing` decides, whatever the
+            // handler set.
+            if key.eq_ignore_ascii_case("Transfer-Encoding")
+                || key.eq_ignore_ascii_case("Content-Length")
+            {
+                continue;
+            }
+            output += key;
+            output += ": ";
+            output += value;
+            output += "\r\n";
+        }
+        if !self.headers.contains_key("Date") {
+            output += "Date: ";
+            output += &http_date(SystemTime::now());
             output += "\r\n";
-            output += &self.headers.iter().map(|x| {
-                let mut o = String::new();
-                o += &x.0;
-                o += ": ";
-                o += &x.1;
-                return o;
-            }).collect::<Vec<String>>().join("\n");
-        }
-        output += "\r\n\r\n";
+        }
+        if !self.headers.contains_key("Server") {
+            output += "Server: airline\r\n";
+        }
+        // Clients need the length to find the end of the body
+        // on a connection that stays open.
+        match self.framing() {
+            Framing::Length(length) => {
+                output += "Content-Length: ";
+                output += &length.to_string();
+                output += "\r\n";
+            }
+            Framing::Chunked => output += "Transfer-Encoding: chunked\r\n",
+            Framing::None | Framing::Close => {}
+        }
+        output += "\r\n";
         return output.into_bytes();
     }
 
@@ -63,16 +218,114 @@ impl Response {
     }
 
     pub fn header<S: AsRef<str>, T: AsRef<str>>(mut self, key: S, value: T) -> Response {
-        self.headers.insert(key.as_ref().to_string(), value.as_ref().to_string());
+        self.headers.insert(key, value);
         self
     }
 
+    // Add another value for a header instead of replacing it,
+    // e.g. for several Set-Cookie headers.
+    pub fn append_header<S: AsRef<str>, T: AsRef<str>>(mut self, key: S, value: T) -> Response {
+        self.headers.append(key, value);
+        self
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
     // Get bytes out
     pub fn bytes(self) -> Vec<u8> {
         return self.data;
     }
+
+    pub fn take_stream(&mut self) -> Option<ResponseStream> {
+        self.stream.take()
+    }
+
+    pub fn is_stream(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // Send a streamed body without chunked framing, ending it by closing
+    // the connection. For HTTP/1.0 clients, which can't decode chunks.
+    pub(crate) fn delimit_by_close(mut self) -> Response {
+        if self.stream.is_some() {
+            self.delimited_by_close = true;
+        }
+        self
+    }
+
+    // Used to answer HEAD requests: keep the length of the body
+    // that would have been sent, but not the body itself.
+    pub fn without_body(mut self) -> Response {
+        // The length of a stream isn't known up front, so its headers
+        // are sent as they are.
+        if self.stream.take().is_some() {
+            return self;
+        }
+        self.omitted_length.get_or_insert(self.data.len());
+        self.data = Vec::new();
+        self
+    }
+
+    // Settle what is sent in answer to a request. Streams can't be sent
+    // chunked to HTTP/1.0 clients, so they end by closing the connection.
+    // A body is never sent for HEAD, nor for statuses that forbid one,
+    // where it would be read as the start of the next response.
+    pub(crate) fn for_request(mut self, head_only: bool, http_1_0: bool) -> Response {
+        if http_1_0 {
+            self = self.delimit_by_close();
+        }
+        if head_only || self.is_bodiless() {
+            self = self.without_body();
+        }
+        self
+    }
+
+    // Whether the status forbids a body, as 204 and 304 do.
+    pub fn is_bodiless(&self) -> bool {
+        self.status.is_bodiless()
+    }
+
+    // How the end of the body is marked. Both the headers and the
+    // writer a stream is sent through follow this, so they can't disagree.
+    pub(crate) fn framing(&self) -> Framing {
+        if self.status.is_bodiless() {
+            return Framing::None;
+        }
+        if self.delimited_by_close {
+            return Framing::Close;
+        }
+        if self.streamed {
+            return Framing::Chunked;
+        }
+        Framing::Length(self.omitted_length.unwrap_or(self.data.len()))
+    }
+}
+impl Default for Response {
+    fn default() -> Response {
+        Response::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+    // No body follows, as for 204 and 304.
+    None,
+    // Content-Length, for bodies held in memory.
+    Length(usize),
+    // Transfer-Encoding: chunked, for streams.
+    Chunked,
+    // The body ends when the connection is closed, for streams
+    // sent to HTTP/1.0 clients.
+    Close,
 }
 
+#[derive(Debug, Clone)]
 pub struct RouteError {
     pub message: String,
     pub status_code: ResponseStatusCode,
@@ -130,18 +383,20 @@ impl RouteError {
         o
     }
 
-    pub fn header(&self) -> Vec<u8> {
-        let mut output = String::from("HTTP/1.1 ");
-        output += &self.status_code.http_string();
-        if !self.override_output {
-            output += "\r\nContent-Type: application/json; charset=utf-8";
+    pub fn response(&self) -> Response {
+        let response = Response::data(self.output().into_bytes()).status(self.status_code.clone());
+        if self.override_output {
+            return response;
         }
-        output += "\r\n\r\n";
-        return output.into_bytes();
+        response.header("Content-Type", "application/json; charset=utf-8")
+    }
+
+    pub fn header(&self) -> Vec<u8> {
+        self.response().get_header_data()
     }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub enum ResponseStatusCode {
     Ok,
     Created,
@@ -178,6 +433,7 @@ pub enum ResponseStatusCode {
     TooEarly,
     PreconditionRequired,
     TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
     NotImplemented,
     BadGateway,
@@ -186,6 +442,14 @@ pub enum ResponseStatusCode {
     HttpVersionNotSupported,
 }
 impl ResponseStatusCode {
+    // 204 and 304 responses never carry a body, nor a length for one.
+    pub fn is_bodiless(&self) -> bool {
+        matches!(
+            self,
+            ResponseStatusCode::NoContent | ResponseStatusCode::NotModified
+        )
+    }
+
     pub fn code(&self) -> i32 {
         match self {
             ResponseStatusCode::Ok => 200,
@@ -223,6 +487,7 @@ impl ResponseStatusCode {
             ResponseStatusCode::TooEarly => 425,
             ResponseStatusCode::PreconditionRequired => 428,
             ResponseStatusCode::TooManyRequests => 429,
+            ResponseStatusCode::RequestHeaderFieldsTooLarge => 431,
             ResponseStatusCode::InternalServerError => 500,
             ResponseStatusCode::NotImplemented => 501,
             ResponseStatusCode::BadGateway => 502,
@@ -270,6 +535,9 @@ impl ResponseStatusCode {
             ResponseStatusCode::TooEarly => "425 Too Early".to_string(),
             ResponseStatusCode::PreconditionRequired => "428 Precondition Required".to_string(),
             ResponseStatusCode::TooManyRequests => "429 Too Many Requests".to_string(),
+            ResponseStatusCode::RequestHeaderFieldsTooLarge => {
+                "431 Request Header Fields Too Large".to_string()
+            }
             ResponseStatusCode::InternalServerError => "500 Internal Server Error".to_string(),
             ResponseStatusCode::NotImplemented => "501 Not Implemented".to_string(),
             ResponseStatusCode::BadGateway => "502 Bad Gateway".to_string(),
@@ -288,6 +556,8 @@ pub enum BodyContents {
     JsonObject(JsonObject),
     JsonArray(JsonArray),
     PlainText(String),
+    Form(QueryMap),
+    Multipart(Multipart),
     None,
 }
 impl BodyContents {
@@ -295,22 +565,64 @@ impl BodyContents {
     const TYPE_OCTET_STREAM: &str = "application/octet-stream";
     const TYPE_LD_JSON: &str = "application/ld+json";
     const TYPE_PLAIN_TEXT: &str = "text/plain";
+    const TYPE_FORM: &str = "application/x-www-form-urlencoded";
+    const TYPE_MULTIPART: &str = "multipart/form-data";
 
-    pub fn type_from_mime(mime: &str, data: Vec<u8>) -> BodyContents {
-        match mime {
-            BodyContents::TYPE_OCTET_STREAM => BodyContents::Binary(data),
+    // Decode a body by its media type. Multipart bodies need its
+    // boundary parameter, text bodies its charset.
+    pub fn from_media_type(
+        media_type: Option<&MediaType>,
+        data: &[u8],
+        options: &MultipartOptions,
+    ) -> Result<BodyContents, RouteError> {
+        let Some(media_type) = media_type else {
+            return BodyContents::type_from_mime("", data);
+        };
+        let charset = media_type.charset();
+        match media_type.essence().as_str() {
+            BodyContents::TYPE_MULTIPART => {
+                let boundary = media_type
+                    .param("boundary")
+                    .ok_or(RouteError::bad_request("Missing multipart boundary"))?;
+                let multipart = Multipart::parse(data, boundary, options)?;
+                Ok(BodyContents::Multipart(multipart))
+            }
             BodyContents::TYPE_JSON | BodyContents::TYPE_LD_JSON => {
-                let contents_string = String::from_utf8(data).unwrap();
-                if contents_string.chars().next().unwrap() == '[' {
-                    BodyContents::JsonArray(JsonArray::from_string(contents_string))
-                } else {
-                    BodyContents::JsonObject(JsonObject::from_string(contents_string))
-                }
+                BodyContents::json(decode_text(data, charset.as_deref())?)
             }
-            BodyContents::TYPE_PLAIN_TEXT => {
-                BodyContents::PlainText(String::from_utf8(data).unwrap())
+            // Types like application/vnd.api+json are JSON too.
+            _ if media_type.suffix.as_deref() == Some("json") => {
+                BodyContents::json(decode_text(data, charset.as_deref())?)
             }
-            _ => BodyContents::Binary(data),
+            BodyContents::TYPE_PLAIN_TEXT => Ok(BodyContents::PlainText(decode_text(
+                data,
+                charset.as_deref(),
+            )?)),
+            mime => BodyContents::type_from_mime(mime, data),
+        }
+    }
+
+    pub fn type_from_mime(mime: &str, data: &[u8]) -> Result<BodyContents, RouteError> {
+        match mime {
+            BodyContents::TYPE_OCTET_STREAM => Ok(BodyContents::Binary(data.to_vec())),
+            BodyContents::TYPE_JSON | BodyContents::TYPE_LD_JSON => {
+                BodyContents::json(decode_text(data, None)?)
+            }
+            BodyContents::TYPE_PLAIN_TEXT => Ok(BodyContents::PlainText(decode_text(data, None)?)),
+            // Forms are encoded just like a query string.
+            BodyContents::TYPE_FORM => Ok(BodyContents::Form(QueryMap::from_string(
+                &String::from_utf8_lossy(data),
+            ))),
+            _ => Ok(BodyContents::Binary(data.to_vec())),
+        }
+    }
+
+    fn json(contents_string: String) -> Result<BodyContents, RouteError> {
+        check_syntax(&contents_string).map_err(RouteError::bad_request)?;
+        match contents_string.trim_start().chars().next() {
+            Some('[') => Ok(BodyContents::JsonArray(JsonArray::from_string(contents_string))),
+            Some('{') => Ok(BodyContents::JsonObject(JsonObject::from_string(contents_string))),
+            _ => Err(RouteError::bad_request("Expected a JSON object or array")),
         }
     }
 
@@ -338,6 +650,30 @@ impl BodyContents {
             _ => JsonArray::from_string("[]".to_string()),
         }
     }
+    pub fn form(&self) -> Option<&QueryMap> {
+        match self {
+            BodyContents::Form(f) => Some(f),
+            _ => None,
+        }
+    }
+    pub fn as_form(self) -> QueryMap {
+        match self {
+            BodyContents::Form(f) => f,
+            _ => QueryMap::new(),
+        }
+    }
+    pub fn multipart(&self) -> Option<&Multipart> {
+        match self {
+            BodyContents::Multipart(m) => Some(m),
+            _ => None,
+        }
+    }
+    pub fn as_multipart(self) -> Multipart {
+        match self {
+            BodyContents::Multipart(m) => m,
+            _ => Multipart::new(),
+        }
+    }
     pub fn as_bytes(self) -> Vec<u8> {
         match self {
             BodyContents::Binary(j) => j,
@@ -346,12 +682,18 @@ impl BodyContents {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RequestType {
     Get,
     Post,
     Put,
     Delete,
+    Patch,
+    Head,
+    Options,
+    Connect,
+    Trace,
+    Other(String),
     Any,
 }
 
@@ -360,6 +702,11 @@ impl RequestType {
     const POST_TYPE: &str = "POST";
     const PUT_TYPE: &str = "PUT";
     const DELETE_TYPE: &str = "DELETE";
+    const PATCH_TYPE: &str = "PATCH";
+    const HEAD_TYPE: &str = "HEAD";
+    const OPTIONS_TYPE: &str = "OPTIONS";
+    const CONNECT_TYPE: &str = "CONNECT";
+    const TRACE_TYPE: &str = "TRACE";
     const ANY_TYPE: &str = "ANY";
 
     pub fn type_for_method(method: &str) -> RequestType {
@@ -368,27 +715,107 @@ impl RequestType {
             RequestType::POST_TYPE => RequestType::Post,
             RequestType::PUT_TYPE => RequestType::Put,
             RequestType::DELETE_TYPE => RequestType::Delete,
-            _ => RequestType::Any,
+            RequestType::PATCH_TYPE => RequestType::Patch,
+            RequestType::HEAD_TYPE => RequestType::Head,
+            RequestType::OPTIONS_TYPE => RequestType::Options,
+            RequestType::CONNECT_TYPE => RequestType::Connect,
+            RequestType::TRACE_TYPE => RequestType::Trace,
+            _ => RequestType::Other(method.to_string()),
         }
     }
 
     pub fn is_any(&self) -> bool {
-        match self {
-            RequestType::Get => false,
-            RequestType::Post => false,
-            RequestType::Put => false,
-            RequestType::Delete => false,
-            RequestType::Any => true,
-        }
+        matches!(self, RequestType::Any)
+    }
+}
+impl Display for RequestType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let method = match self {
+            RequestType::Get => RequestType::GET_TYPE,
+            RequestType::Post => RequestType::POST_TYPE,
+            RequestType::Put => RequestType::PUT_TYPE,
+            RequestType::Delete => RequestType::DELETE_TYPE,
+            RequestType::Patch => RequestType::PATCH_TYPE,
+            RequestType::Head => RequestType::HEAD_TYPE,
+            RequestType::Options => RequestType::OPTIONS_TYPE,
+            RequestType::Connect => RequestType::CONNECT_TYPE,
+            RequestType::Trace => RequestType::TRACE_TYPE,
+            RequestType::Other(method) => method,
+            RequestType::Any => RequestType::ANY_TYPE,
+        };
+        f.write_str(method)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Response, ResponseStatusCode};
+
+    // The response as the worker writes it, streams left out.
+    fn serialize(response: Response, head_only: bool) -> String {
+        let mut response = response.for_request(head_only, false);
+        let mut output = response.get_header_data();
+        assert!(response.take_stream().is_none());
+        output.append(&mut response.bytes());
+        String::from_utf8(output).unwrap()
     }
 
-    pub fn to_string(&self) -> String {
-        match self {
-            RequestType::Get => RequestType::GET_TYPE.to_string(),
-            RequestType::Post => RequestType::POST_TYPE.to_string(),
-            RequestType::Put => RequestType::PUT_TYPE.to_string(),
-            RequestType::Delete => RequestType::DELETE_TYPE.to_string(),
-            RequestType::Any => RequestType::ANY_TYPE.to_string(),
+    fn stream() -> Response {
+        Response::chunks(vec![b"abc".to_vec()])
+    }
+
+    #[test]
+    fn bodiless_statuses_send_no_body_or_framing() {
+        for status in [
+            ResponseStatusCode::NoContent,
+            ResponseStatusCode::NotModified,
+        ] {
+            for response in [
+                Response::string("deleted"),
+                Response::string("deleted").header("Content-Length", "7"),
+                stream().header("Transfer-Encoding", "chunked"),
+            ] {
+                let output = serialize(response.status(status.clone()), false);
+                assert!(output.ends_with("\r\n\r\n"), "{}", output);
+                assert!(!output.contains("Content-Length"), "{}", output);
+                assert!(!output.contains("Transfer-Encoding"), "{}", output);
+            }
         }
     }
+
+    #[test]
+    fn bodies_in_memory_are_framed_by_their_length() {
+        let output = serialize(
+            Response::string("hello").header("Content-Length", "2"),
+            false,
+        );
+        assert!(output.contains("\r\nContent-Length: 5\r\n"), "{}", output);
+        assert!(!output.contains("Content-Length: 2"), "{}", output);
+        assert!(output.ends_with("\r\n\r\nhello"), "{}", output);
+        let output = serialize(
+            Response::string("abc").header("Transfer-Encoding", "chunked"),
+            false,
+        );
+        assert!(!output.contains("Transfer-Encoding"), "{}", output);
+        // HEAD keeps the length of the body it leaves out.
+        let output = serialize(Response::string("hello"), true);
+        assert!(output.contains("\r\nContent-Length: 5\r\n"), "{}", output);
+        assert!(output.ends_with("\r\n\r\n"), "{}", output);
+    }
+
+    #[test]
+    fn streams_are_chunked_whatever_the_handler_set() {
+        let response = stream().header("Content-Length", "3");
+        let output = String::from_utf8(response.get_header_data()).unwrap();
+        assert!(
+            output.contains("\r\nTransfer-Encoding: chunked\r\n"),
+            "{}",
+            output
+        );
+        assert!(!output.contains("Content-Length"), "{}", output);
+        let response = stream().for_request(false, true);
+        let output = String::from_utf8(response.get_header_data()).unwrap();
+        assert!(!output.contains("Transfer-Encoding"), "{}", output);
+        assert!(!output.contains("Content-Length"), "{}", output);
+    }
 }
@@ -0,0 +1,498 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+
+use super::{query::percent_decode, RequestType, Route};
+
+// Routes are stored in a tree keyed by path segment, shared across
+// every method. Each node holds the routes that end at it, so a lookup
+// only walks as many nodes as the request path has segments.
+pub struct RouteStorage<T: 'static + Send> {
+    routes: Vec<Route<T>>,
+    root: RouteNode,
+}
+
+impl<T: 'static + Send> RouteStorage<T> {
+    pub(crate) fn new() -> RouteStorage<T> {
+        RouteStorage {
+            routes: Vec::new(),
+            root: RouteNode::new(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, route: Route<T>) {
+        self.routes.push(route);
+    }
+
+    pub(crate) fn prep(&mut self) -> Result<(), RouterError> {
+        let mut root = RouteNode::new();
+        for (ix, route) in self.routes.iter().enumerate() {
+            let segments = PathSegment::parse(&route.path)?;
+            let routes = root.insert(&route.path, &segments)?;
+            if let Some(existing) = routes
+                .iter()
+                .find(|r| self.routes[**r].request_type == route.request_type)
+            {
+                return Err(RouterError::DuplicateRoute {
+                    request_type: route.request_type.clone(),
+                    path: route.path.clone(),
+                    existing: self.routes[*existing].path.clone(),
+                });
+            }
+            routes.push(ix);
+        }
+        self.root = root;
+        Ok(())
+    }
+
+    // The path is split as sent and each segment decoded on its own,
+    // so an escaped "/" is part of a segment rather than a separator.
+    pub(crate) fn handler(&self, request_type: &RequestType, raw_path: &str) -> RouteMatch<'_, T> {
+        let decoded = raw_path
+            .split('/')
+            .filter(|p| !p.is_empty())
+            .map(|p| percent_decode(p, false))
+            .collect::<Vec<String>>();
+        let parts = decoded.iter().map(|p| p.as_str()).collect::<Vec<&str>>();
+        let mut found = Vec::new();
+        self.root.lookup(&parts, &mut Vec::new(), &mut found);
+        if found.is_empty() {
+            return RouteMatch::NotFound;
+        }
+        let find = |routes: &[usize], request_type: &RequestType| {
+            routes
+                .iter()
+                .map(|ix| &self.routes[*ix])
+                .find(|r| r.request_type == *request_type)
+        };
+
+        // A branch only wins if it can handle the method, otherwise the
+        // next matching branch is tried. HEAD falls back to the GET
+        // handler and OPTIONS is answered from the registered methods,
+        // unless either has a route of its own. Routes for any method
+        // get both, as they do every other method.
+        for (routes, params) in &found {
+            let route = match request_type {
+                RequestType::Head => find(routes, &RequestType::Head)
+                    .or_else(|| find(routes, &RequestType::Get))
+                    .or_else(|| find(routes, &RequestType::Any)),
+                RequestType::Options => {
+                    find(routes, &RequestType::Options).or_else(|| find(routes, &RequestType::Any))
+                }
+                _ => find(routes, request_type).or_else(|| find(routes, &RequestType::Any)),
+            };
+            if let Some(route) = route {
+                return RouteMatch::Found(route, params.iter().cloned().collect());
+            }
+        }
+        // The path exists, just not for this method.
+        let allowed = RouteStorage::allowed(
+            found
+                .iter()
+                .flat_map(|(routes, _)| routes.iter().map(|ix| &self.routes[*ix])),
+        );
+        match request_type {
+            RequestType::Options => RouteMatch::Options(allowed),
+            _ => RouteMatch::MethodNotAllowed(allowed),
+        }
+    }
+
+    fn allowed<'a>(routes: impl Iterator<Item = &'a Route<T>>) -> Vec<RequestType> {
+        let mut allowed: Vec<RequestType> = Vec::new();
+        for route in routes {
+            if !route.request_type.is_any() && !allowed.contains(&route.request_type) {
+                allowed.push(route.request_type.clone());
+            }
+        }
+        if allowed.contains(&RequestType::Get) && !allowed.contains(&RequestType::Head) {
+            allowed.push(RequestType::Head);
+        }
+        if !allowed.contains(&RequestType::Options) {
+            allowed.push(RequestType::Options);
+        }
+        return allowed;
+    }
+}
+
+impl<T: 'static + Send> Default for RouteStorage<T> {
+    fn default() -> RouteStorage<T> {
+        RouteStorage::new()
+    }
+}
+
+pub(crate) enum RouteMatch<'a, T: 'static + Send> {
+    Found(&'a Route<T>, HashMap<String, String>),
+    MethodNotAllowed(Vec<RequestType>),
+    Options(Vec<RequestType>),
+    NotFound,
+}
+
+// Parameters captured while walking the tree, in path order.
+type Params = Vec<(String, String)>;
+
+struct RouteNode {
+    routes: Vec<usize>,
+    static_children: HashMap<String, RouteNode>,
+    param_child: Option<(String, Box<RouteNode>)>,
+    wildcard: Option<(String, Vec<usize>)>,
+}
+
+impl RouteNode {
+    fn new() -> RouteNode {
+        RouteNode {
+            routes: Vec::new(),
+            static_children: HashMap::new(),
+            param_child: None,
+            wildcard: None,
+        }
+    }
+
+    // Walk (and build) the tree for a route's segments, returning
+    // the list of routes registered at the final node.
+    fn insert(
+        &mut self,
+        path: &str,
+        segments: &[PathSegment],
+    ) -> Result<&mut Vec<usize>, RouterError> {
+        let Some(segment) = segments.first() else {
+            return Ok(&mut self.routes);
+        };
+        match segment {
+            PathSegment::Static(s) => self
+                .static_children
+                .entry(s.clone())
+                .or_insert_with(RouteNode::new)
+                .insert(path, &segments[1..]),
+            PathSegment::Param(name) => {
+                let (existing, child) = self
+                    .param_child
+                    .get_or_insert_with(|| (name.clone(), Box::new(RouteNode::new())));
+                if existing != name {
+                    return Err(RouterError::ConflictingName {
+                        path: path.to_string(),
+                        existing: existing.clone(),
+                        name: name.clone(),
+                    });
+                }
+                child.insert(path, &segments[1..])
+            }
+            PathSegment::Wildcard(name) => {
+                let (existing, routes) = self
+                    .wildcard
+                    .get_or_insert_with(|| (name.clone(), Vec::new()));
+                if existing != name {
+                    return Err(RouterError::ConflictingName {
+                        path: path.to_string(),
+                        existing: existing.clone(),
+                        name: name.clone(),
+                    });
+                }
+                Ok(routes)
+            }
+        }
+    }
+
+    // Collect every branch that matches, most specific first: static
+    // segments win over parameters, which win over wildcards. Each
+    // match keeps the parameters captured on the way to it.
+    fn lookup<'a>(
+        &'a self,
+        parts: &[&str],
+        params: &mut Params,
+        found: &mut Vec<(&'a Vec<usize>, Params)>,
+    ) {
+        if let Some((part, rest)) = parts.split_first() {
+            if let Some(child) = self.static_children.get(*part) {
+                child.lookup(rest, params, found);
+            }
+            if let Some((name, child)) = &self.param_child {
+                params.push((name.clone(), part.to_string()));
+                child.lookup(rest, params, found);
+                params.pop();
+            }
+        } else if !self.routes.is_empty() {
+            found.push((&self.routes, params.clone()));
+        }
+        if let Some((name, routes)) = &self.wildcard {
+            let mut params = params.clone();
+            params.push((name.clone(), parts.join("/")));
+            found.push((routes, params));
+        }
+    }
+}
+
+enum PathSegment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+impl PathSegment {
+    // Split a route path such as "/users/:id/*rest" into segments.
+    fn parse(path: &str) -> Result<Vec<PathSegment>, RouterError> {
+        let mut segments = Vec::new();
+        let parts = path
+            .split('/')
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<&str>>();
+        for (ix, part) in parts.iter().enumerate() {
+            if let Some(name) = part.strip_prefix(':') {
+                if name.is_empty() {
+                    return Err(RouterError::MissingName {
+                        path: path.to_string(),
+                    });
+                }
+                segments.push(PathSegment::Param(name.to_string()));
+            } else if let Some(name) = part.strip_prefix('*') {
+                if name.is_empty() {
+                    return Err(RouterError::MissingName {
+                        path: path.to_string(),
+                    });
+                }
+                if ix != parts.len() - 1 {
+                    return Err(RouterError::WildcardNotLast {
+                        path: path.to_string(),
+                    });
+                }
+                segments.push(PathSegment::Wildcard(name.to_string()));
+            } else {
+                segments.push(PathSegment::Static(part.to_string()));
+            }
+        }
+        return Ok(segments);
+    }
+}
+
+#[derive(Debug)]
+pub enum RouterError {
+    DuplicateRoute {
+        request_type: RequestType,
+        path: String,
+        existing: String,
+    },
+    ConflictingName {
+        path: String,
+        existing: String,
+        name: String,
+    },
+    WildcardNotLast {
+        path: String,
+    },
+    MissingName {
+        path: String,
+    },
+}
+impl Display for RouterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RouterError::DuplicateRoute {
+                request_type,
+                path,
+                existing,
+            } => write!(
+                f,
+                "{} {} is already handled by the route registered as {}",
+                request_type, path, existing
+            ),
+            RouterError::ConflictingName {
+                path,
+                existing,
+                name,
+            } => write!(
+                f,
+                "{} names a segment '{}' where another route already named it '{}'",
+                path, name, existing
+            ),
+            RouterError::WildcardNotLast { path } => {
+                write!(f, "{} has a wildcard that is not the last segment", path)
+            }
+            RouterError::MissingName { path } => {
+                write!(f, "{} has a parameter or wildcard without a name", path)
+            }
+        }
+    }
+}
+impl std::error::Error for RouterError {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::super::{Request, RequestType, Response, Route, RouteError};
+    use super::{RouteMatch, RouteStorage, RouterError};
+
+    fn ok(_: Request, _: &()) -> Result<Response, RouteError> {
+        Ok(Response::new())
+    }
+
+    fn storage(routes: &[(RequestType, &str)]) -> RouteStorage<()> {
+        let mut storage = RouteStorage::new();
+        for (request_type, path) in routes {
+            storage.add(Route::create(path, request_type.clone(), ok));
+        }
+        storage.prep().unwrap();
+        storage
+    }
+
+    fn found(
+        storage: &RouteStorage<()>,
+        request_type: RequestType,
+        path: &str,
+    ) -> (String, HashMap<String, String>) {
+        match storage.handler(&request_type, path) {
+            RouteMatch::Found(route, params) => (route.path.clone(), params),
+            _ => panic!("{} {} did not match a route", request_type, path),
+        }
+    }
+
+    fn allowed(storage: &RouteStorage<()>, request_type: RequestType, path: &str) -> Vec<String> {
+        let allowed = match storage.handler(&request_type, path) {
+            RouteMatch::MethodNotAllowed(allowed) | RouteMatch::Options(allowed) => allowed,
+            _ => panic!(
+                "{} {} was not answered with the allowed methods",
+                request_type, path
+            ),
+        };
+        let mut allowed: Vec<String> = allowed.iter().map(|m| m.to_string()).collect();
+        allowed.sort();
+        allowed
+    }
+
+    #[test]
+    fn static_segments_win_over_params_and_wildcards() {
+        let storage = storage(&[
+            (RequestType::Get, "/users/me"),
+            (RequestType::Get, "/users/:id"),
+            (RequestType::Get, "/users/*rest"),
+        ]);
+        assert_eq!(
+            found(&storage, RequestType::Get, "/users/me").0,
+            "/users/me"
+        );
+        let (path, params) = found(&storage, RequestType::Get, "/users/42");
+        assert_eq!(path, "/users/:id");
+        assert_eq!(params["id"], "42");
+        let (path, params) = found(&storage, RequestType::Get, "/users/42/posts");
+        assert_eq!(path, "/users/*rest");
+        assert_eq!(params["rest"], "42/posts");
+    }
+
+    #[test]
+    fn dead_ends_backtrack_to_the_next_branch() {
+        let storage = storage(&[(RequestType::Get, "/a/b/c"), (RequestType::Get, "/a/:x/d")]);
+        let (path, params) = found(&storage, RequestType::Get, "/a/b/d");
+        assert_eq!(path, "/a/:x/d");
+        assert_eq!(params["x"], "b");
+    }
+
+    #[test]
+    fn methods_fall_back_to_less_specific_branches() {
+        let storage = storage(&[
+            (RequestType::Get, "/users/me"),
+            (RequestType::Delete, "/users/:id"),
+            (RequestType::Post, "/users/*rest"),
+        ]);
+        let (path, params) = found(&storage, RequestType::Delete, "/users/me");
+        assert_eq!(path, "/users/:id");
+        assert_eq!(params["id"], "me");
+        let (path, params) = found(&storage, RequestType::Post, "/users/me");
+        assert_eq!(path, "/users/*rest");
+        assert_eq!(params["rest"], "me");
+        assert_eq!(
+            allowed(&storage, RequestType::Put, "/users/me"),
+            ["DELETE", "GET", "HEAD", "OPTIONS", "POST"]
+        );
+        assert_eq!(
+            allowed(&storage, RequestType::Options, "/users/me"),
+            ["DELETE", "GET", "HEAD", "OPTIONS", "POST"]
+        );
+    }
+
+    #[test]
+    fn head_and_any_fall_back_within_a_branch() {
+        let storage = storage(&[(RequestType::Get, "/a"), (RequestType::Any, "/b")]);
+        assert_eq!(found(&storage, RequestType::Head, "/a").0, "/a");
+        assert_eq!(found(&storage, RequestType::Put, "/b").0, "/b");
+        assert_eq!(found(&storage, RequestType::Options, "/b").0, "/b");
+        assert_eq!(
+            allowed(&storage, RequestType::Options, "/a"),
+            ["GET", "HEAD", "OPTIONS"]
+        );
+        assert!(matches!(
+            storage.handler(&RequestType::Get, "/c"),
+            RouteMatch::NotFound
+        ));
+    }
+
+    #[test]
+    fn segments_are_decoded_after_splitting() {
+        let storage = storage(&[
+            (RequestType::Get, "/files/a/b"),
+            (RequestType::Get, "/files/:name"),
+            (RequestType::Get, "/café"),
+            (RequestType::Get, "/docs/*rest"),
+        ]);
+        let (path, params) = found(&storage, RequestType::Get, "/files/a%2Fb");
+        assert_eq!(path, "/files/:name");
+        assert_eq!(params["name"], "a/b");
+        assert_eq!(
+            found(&storage, RequestType::Get, "/files/a/b").0,
+            "/files/a/b"
+        );
+        assert_eq!(found(&storage, RequestType::Get, "/caf%C3%A9").0, "/café");
+        let (_, params) = found(&storage, RequestType::Get, "/docs/a%20b/c");
+        assert_eq!(params["rest"], "a b/c");
+    }
+
+    #[test]
+    fn duplicate_routes_are_rejected() {
+        let mut storage: RouteStorage<()> = RouteStorage::new();
+        storage.add(Route::create("/users/:id", RequestType::Get, ok));
+        storage.add(Route::create("users/:id/", RequestType::Get, ok));
+        assert!(matches!(
+            storage.prep(),
+            Err(RouterError::DuplicateRoute { .. })
+        ));
+
+        let mut storage: RouteStorage<()> = RouteStorage::new();
+        storage.add(Route::create("/users/:id", RequestType::Get, ok));
+        storage.add(Route::create("/users/:id", RequestType::Post, ok));
+        assert!(storage.prep().is_ok());
+    }
+
+    #[test]
+    fn conflicting_segment_names_are_rejected() {
+        let mut storage: RouteStorage<()> = RouteStorage::new();
+        storage.add(Route::create("/users/:id", RequestType::Get, ok));
+        storage.add(Route::create("/users/:name/posts", RequestType::Get, ok));
+        assert!(matches!(
+            storage.prep(),
+            Err(RouterError::ConflictingName { .. })
+        ));
+
+        let mut storage: RouteStorage<()> = RouteStorage::new();
+        storage.add(Route::create("/files/*rest", RequestType::Get, ok));
+        storage.add(Route::create("/files/*path", RequestType::Post, ok));
+        assert!(matches!(
+            storage.prep(),
+            Err(RouterError::ConflictingName { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed_patterns_are_rejected() {
+        let mut storage: RouteStorage<()> = RouteStorage::new();
+        storage.add(Route::create("/files/*rest/edit", RequestType::Get, ok));
+        assert!(matches!(
+            storage.prep(),
+            Err(RouterError::WildcardNotLast { .. })
+        ));
+
+        let mut storage: RouteStorage<()> = RouteStorage::new();
+        storage.add(Route::create("/users/:", RequestType::Get, ok));
+        assert!(matches!(
+            storage.prep(),
+            Err(RouterError::MissingName { .. })
+        ));
+    }
+}
@@ -1,119 +1,173 @@
 use std::{
-    io::{BufReader, Read},
-    net::{TcpListener, TcpStream},
+    cell::OnceCell,
+    io::{self, BufReader},
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    sync::{mpsc::Sender, Arc},
+    thread,
+    time::Duration,
 };
 
-use super::{BodyContents, Request, RequestQueue, RequestType, Response, ResponseStatusCode, WorkerSetupFn, RouteError};
+#[cfg(unix)]
+use std::{os::fd::OwnedFd, path::Path};
+
+#[cfg(unix)]
+use super::UnixSocketOptions;
+use super::{
+    connection::Listener,
+    parser::{body_reader, read_request_body, read_request_head, ParseError},
+    router::{RouteMatch, RouteStorage},
+    BodyContents, Multipart, MultipartError,
+    MultipartOptions, Request, RequestLimits, RequestQueue, RequestType, Response, ResponseStatusCode, RouteError,
+    ServerConfig, TimedStream, WorkerSetupFn,
+};
 
 pub struct Server<T: 'static + Send> {
     routes: RouteStorage<T>,
-    listener: TcpListener,
-    request_queue: RequestQueue<T>,
+    listeners: Vec<Listener>,
+    setup_fn: WorkerSetupFn<T>,
+    config: ServerConfig,
 }
 impl<T: 'static + Send> Server<T> {
+    // Listen on localhost only. Panics if the port can't be bound,
+    // use `bind` to handle that yourself.
     pub fn new(port: i32, setup_fn: WorkerSetupFn<T>) -> Server<T> {
+        Server::bind(format!("127.0.0.1:{}", port), setup_fn).unwrap()
+    }
+
+    // Listen on any address, e.g. "0.0.0.0:8080" or "[::1]:8080".
+    pub fn bind(addr: impl ToSocketAddrs, setup_fn: WorkerSetupFn<T>) -> io::Result<Server<T>> {
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        Ok(Server::from_listener(listener, setup_fn))
+    }
+
+    // Listen on a Unix domain socket, e.g. behind a reverse proxy.
+    #[cfg(unix)]
+    pub fn bind_unix(
+        path: impl AsRef<Path>,
+        options: UnixSocketOptions,
+        setup_fn: WorkerSetupFn<T>,
+    ) -> io::Result<Server<T>> {
+        let listener = Listener::bind_unix(path.as_ref(), &options)?;
+        Ok(Server::from_listener(listener, setup_fn))
+    }
+
+    // Serve on the sockets passed in by systemd socket activation, so
+    // the socket stays open across restarts and privileged ports can be
+    // used without running as root.
+    #[cfg(unix)]
+    pub fn from_systemd(setup_fn: WorkerSetupFn<T>) -> io::Result<Server<T>> {
+        let mut listeners = Listener::from_systemd()?.into_iter();
+        // from_systemd never returns an empty list.
+        let mut server = Server::from_listener(listeners.next().unwrap(), setup_fn);
+        for listener in listeners {
+            server.add_listener(listener);
+        }
+        Ok(server)
+    }
+
+    // Serve on a listener that was opened elsewhere.
+    pub fn from_listener(listener: Listener, setup_fn: WorkerSetupFn<T>) -> Server<T> {
         Server {
             routes: RouteStorage::new(),
-            listener: TcpListener::bind(format!("127.0.0.1:{}", port)).unwrap(),
-            request_queue: RequestQueue::new(setup_fn),
+            listeners: vec![listener],
+            setup_fn,
+            config: ServerConfig::new(),
         }
     }
 
+    // Accept connections on another address as well. Every listener
+    // serves the same routes from the same workers.
+    pub fn listen(&mut self, addr: impl ToSocketAddrs) -> io::Result<()> {
+        self.listeners.push(Listener::Tcp(TcpListener::bind(addr)?));
+        Ok(())
+    }
+
+    pub fn add_listener(&mut self, listener: Listener) {
+        self.listeners.push(listener);
+    }
+
+    // Accept connections on a socket that is already listening, such as
+    // one inherited from the process that started us.
+    #[cfg(unix)]
+    pub fn listen_fd(&mut self, fd: OwnedFd) -> io::Result<()> {
+        self.listeners.push(Listener::from_fd(fd)?);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, path: impl AsRef<Path>, options: UnixSocketOptions) -> io::Result<()> {
+        self.listeners.push(Listener::bind_unix(path.as_ref(), &options)?);
+        Ok(())
+    }
+
+    // The TCP addresses actually bound, useful after binding to port 0.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().filter_map(|l| l.local_addr()).collect()
+    }
+
     pub fn register(&mut self, r: Route<T>) {
         self.routes.add(r);
     }
-    pub fn start(&mut self) {
-        self.routes.prep();
-        loop {
-            if let Ok(conn) = self.listener.accept() {
-                let (mut req_stream, _) = conn;
-                let req_parsed = self.create_request_object(&mut req_stream);                
-                let mut matched_path: fn(Request, &T) -> Result<Response, RouteError> = Server::default_error;
-                if let Some(handler) = self
-                    .routes
-                    .handler(&req_parsed.request_type, &req_parsed.path)
-                {
-                    matched_path = handler.handler;
-                }
-
-                let req = IncomingRequest {
-                    request: req_parsed,
-                    stream: req_stream,
-                    route: matched_path,
-                };
-                self.request_queue.add(req);
-            }
-        }
+
+    pub fn keep_alive(&mut self, enabled: bool) {
+        self.config.keep_alive = enabled;
+    }
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) {
+        self.config.keep_alive_timeout = timeout;
+    }
+    pub fn max_requests_per_connection(&mut self, max: usize) {
+        self.config.max_requests_per_connection = max;
+    }
+    pub fn limits(&mut self, limits: RequestLimits) {
+        self.config.limits = limits;
+    }
+    pub fn header_read_timeout(&mut self, timeout: Duration) {
+        self.config.header_read_timeout = timeout;
+    }
+    pub fn body_read_timeout(&mut self, timeout: Duration) {
+        self.config.body_read_timeout = timeout;
+    }
+    pub fn write_timeout(&mut self, timeout: Duration) {
+        self.config.write_timeout = timeout;
+    }
+    pub fn worker_threads(&mut self, count: usize) {
+        self.config.worker_threads = Some(count);
+    }
+    pub fn max_connections(&mut self, max: usize) {
+        self.config.max_connections = max;
+    }
+    pub fn multipart(&mut self, options: MultipartOptions) {
+        self.config.multipart = options;
     }
 
-    fn create_request_object(&self, stream: &mut TcpStream) -> Request {
-        let mut buffer = BufReader::new(stream);
-        let mut headers_content = String::new();
-
-        let mut cur_char: [u8; 1] = [0];
-        let mut whitespace_count = 0;
-
-        // Obtain headers
-        loop {
-            if let Ok(_) = buffer.read_exact(&mut cur_char) {
-                let cur_char_val = char::from_u32(cur_char[0] as u32).unwrap();
-                headers_content.push(cur_char_val);
-                if cur_char_val == '\u{a}' || cur_char_val == '\u{d}' {
-                    whitespace_count += 1;
-                } else {
-                    whitespace_count = 0;
-                }
-                // When we have a blank line, exit.
-                if whitespace_count == 4 {
-                    break;
-                }
-            } else {
-                break;
-            }
+    pub fn start(&mut self) {
+        if let Err(e) = self.routes.prep() {
+            panic!("Could not register routes: {}", e);
         }
-        // Process headers
-        let req: Vec<String> = headers_content
-            .lines()
-            .map(|a| a.to_string())
-            .take_while(|a| !a.is_empty())
-            .collect();
-        let head = &req[0].split(" ").collect::<Vec<&str>>();
-
-        let mut created_request = Request {
-            path: head[1].to_string(),
-            request_type: RequestType::type_for_method(head[0]),
-            headers: req[1..]
-                .to_vec()
-                .iter()
-                .map(|a| {
-                    let d: Vec<&str> = a.split(": ").collect();
-                    return (d[0].to_string(), d[1].to_string());
-                })
-                .collect(),
-            body: BodyContents::None,
-        };
-
-        if let Some(content_length_str) = created_request.headers.get("Content-Length") {
-            // We have a body.
-            let content_len: usize = content_length_str.parse().unwrap_or(0);
-            let mut content: Vec<u8> = Vec::new();
-            // Read body
-            loop {
-                if content.len() == content_len { break; }
-                if let Ok(_) = buffer.read_exact(&mut cur_char) {
-                    content.push(cur_char[0]);
-                } else {
-                    break;
-                }
+        // Each connection reads whole requests on its own thread before
+        // a worker answers them, so the listener only accepts connections
+        // and slow clients hold up no worker.
+        let routes = Arc::new(std::mem::take(&mut self.routes));
+        let config = Arc::new(self.config.clone());
+        let request_queue = match self.config.worker_threads {
+            Some(count) => {
+                RequestQueue::new_with_thread_count(self.setup_fn, routes, config, count)
             }
-            if let Some(content_type) = created_request.headers.get("Content-Type") {
-                let no_charset = content_type.split(" ").collect::<Vec<&str>>()[0].replace(";", "");
-                created_request.body = BodyContents::type_from_mime(&no_charset, content);
-            } else {
-                created_request.body = BodyContents::type_from_mime(&String::new(), content);
+            None => RequestQueue::new(self.setup_fn, routes, config),
+        };
+        // Each listener gets its own accept thread, all feeding one queue.
+        thread::scope(|scope| {
+            for listener in &self.listeners {
+                let request_queue = &request_queue;
+                scope.spawn(move || loop {
+                    if let Ok(stream) = listener.accept() {
+                        // Dropping the connection closes it if no thread
+                        // could be started for it.
+                        _ = request_queue.connect(stream);
+                    }
+                });
             }
-        }
-        return created_request;
+        });
     }
 
     fn default_error(_: Request, _: &T) -> Result<Response, RouteError> {
@@ -123,9 +177,11 @@ impl<T: 'static + Send> Server<T> {
 
 
 pub struct Route<T: 'static + Send> {
-    path: String,
-    request_type: RequestType,
-    handler: fn(Request, &T) -> Result<Response, RouteError>,
+    pub(crate) path: String,
+    pub(crate) request_type: RequestType,
+    pub(crate) handler: fn(Request, &T) -> Result<Response, RouteError>,
+    pub(crate) max_body_bytes: Option<usize>,
+    pub(crate) accepts: Option<Vec<String>>,
 }
 impl<T: 'static + Send> Route<T> {
     pub fn create(
@@ -142,8 +198,25 @@ impl<T: 'static + Send> Route<T> {
             path: resolved_path,
             request_type,
             handler,
+            max_body_bytes: None,
+            accepts: None,
         }
     }
+
+    // Override the server's body limit, e.g. for upload endpoints.
+    pub fn max_body_bytes(mut self, max: usize) -> Route<T> {
+        self.max_body_bytes = Some(max);
+        self
+    }
+
+    // Only accept bodies of this media type, e.g. "application/json" or
+    // "image/*". Call again to accept several. Other bodies get a 415.
+    pub fn accepts(mut self, media_type: &str) -> Route<T> {
+        self.accepts
+            .get_or_insert_with(Vec::new)
+            .push(media_type.to_ascii_lowercase());
+        self
+    }
 }
 
 pub trait ToBytes {
@@ -157,75 +230,186 @@ impl ToBytes for String {
 }
 impl ToBytes for &str {
     fn send(self) -> Vec<u8> {
-        self.bytes().into_iter().collect()
+        self.bytes().collect()
     }
 }
 
 pub struct IncomingRequest<T: 'static + Send> {
-    pub request: Request,
-    pub stream: TcpStream,
-    pub route: fn(Request, &T) -> Result<Response, RouteError>,
-}
-
-pub struct RouteStorage<T: 'static + Send> {
-    routes_get: Vec<Route<T>>,
-    routes_post: Vec<Route<T>>,
-    routes_put: Vec<Route<T>>,
-    routes_delete: Vec<Route<T>>,
-    routes_any: Vec<Route<T>>,
+    pub stream: BufReader<TimedStream>,
+    pub(crate) request: Request,
+    pub(crate) route: RouteHandler<T>,
+    // Requests read on this connection, this one included.
+    pub(crate) served: usize,
+    // Where a kept-alive connection goes back to between requests.
+    pub(crate) idle: Sender<BufReader<TimedStream>>,
 }
 
-impl<T: 'static + Send> RouteStorage<T> {
-    fn new() -> RouteStorage<T> {
-        RouteStorage {
-            routes_get: Vec::new(),
-            routes_post: Vec::new(),
-            routes_put: Vec::new(),
-            routes_delete: Vec::new(),
-            routes_any: Vec::new(),
+// Read the next request off a connection. It is routed as soon as the
+// head is in, so the matched route decides how large a body to accept.
+// The head and body each have to arrive within their own timeout.
+// The body is kept as sent and only decoded if the handler asks for it.
+pub(crate) fn next_request<T: 'static + Send>(
+    reader: &mut BufReader<TimedStream>,
+    routes: &RouteStorage<T>,
+    config: &ServerConfig,
+) -> Result<(Request, RouteHandler<T>), ParseError> {
+    reader.get_mut().set_deadline(config.header_read_timeout);
+    let mut request = read_request_head(reader, &config.limits)?;
+    let mut max_body_bytes = config.limits.max_body_bytes;
+    let mut accepts = None;
+    let route = match routes.handler(&request.request_type, &request.raw_path) {
+        RouteMatch::Found(route, params) => {
+            request.params = params;
+            max_body_bytes = route.max_body_bytes.unwrap_or(max_body_bytes);
+            accepts = route.accepts.as_ref();
+            RouteHandler::Route(route.handler)
         }
+        RouteMatch::MethodNotAllowed(allowed) => RouteHandler::MethodNotAllowed(allowed),
+        RouteMatch::Options(allowed) => RouteHandler::Options(allowed),
+        RouteMatch::NotFound => RouteHandler::NotFound,
+    };
+    let accepted = match accepts {
+        Some(accepts) => request
+            .content_type
+            .as_ref()
+            .is_some_and(|media_type| accepts.iter().any(|a| media_type.matches(a))),
+        None => true,
+    };
+    reader.get_mut().set_deadline(config.body_read_timeout);
+    // Uploads that may be spilled to disk are parsed as they arrive,
+    // rather than held in memory whole first.
+    if let Some(boundary) = streamed_boundary(&request, config).filter(|_| accepted) {
+        let Some(mut body) = body_reader(reader, &mut request, &config.limits, max_body_bytes)?
+        else {
+            return Ok((request, route));
+        };
+        let multipart = Multipart::read_from(&mut body, &boundary, &config.multipart);
+        // Read past anything left, even when the body was malformed,
+        // so the next request on the connection starts where it should.
+        io::copy(&mut body, &mut io::sink())?;
+        let contents = match multipart {
+            Ok(multipart) => Ok(BodyContents::Multipart(multipart)),
+            Err(MultipartError::Read(error)) => return Err(error.into()),
+            Err(error) => Err(error.into()),
+        };
+        reader.get_mut().clear_deadline();
+        request.body = OnceCell::from(contents);
+        request.body_streamed = true;
+        return Ok((request, route));
     }
+    let body = read_request_body(reader, &mut request, &config.limits, max_body_bytes)?;
+    reader.get_mut().clear_deadline();
 
-    fn handler(
-        &self,
-        request_type: &RequestType,
-        path: &String,
-    ) -> Option<&Route<T>> {
-        let handler_cat = match request_type {
-            RequestType::Get => &self.routes_get,
-            RequestType::Post => &self.routes_post,
-            RequestType::Put => &self.routes_put,
-            RequestType::Delete => &self.routes_delete,
-            RequestType::Any => &self.routes_any,
+    let Some(content) = body else {
+        return Ok((request, route));
+    };
+    if !accepted {
+        let error = RouteError {
+            message: "Unsupported content type".to_string(),
+            status_code: ResponseStatusCode::UnsupportedMediaType,
+            override_output: false,
         };
-        if let Ok(handler_ix) = handler_cat.binary_search_by(|a| a.path.cmp(path)) {
-            Some(&handler_cat[handler_ix])
-        } else if !request_type.is_any() {
-            let any_ix = self
-                .routes_any
-                .binary_search_by(|a| a.path.cmp(path))
-                .ok()?;
-            Some(&self.routes_any[any_ix])
-        } else {
-            None
+        return Ok((request, RouteHandler::Reject(error)));
+    }
+    request.raw_body = Some(content);
+    request.multipart = config.multipart.clone();
+    Ok((request, route))
+}
+
+// The boundary of a multipart/form-data body whose parts may be
+// spilled to disk.
+fn streamed_boundary(request: &Request, config: &ServerConfig) -> Option<String> {
+    config.multipart.spill_threshold?;
+    let media_type = request.content_type.as_ref()?;
+    if media_type.essence() != "multipart/form-data" {
+        return None;
+    }
+    media_type.param("boundary").map(|b| b.to_string())
+}
+
+pub enum RouteHandler<T: 'static + Send> {
+    Route(fn(Request, &T) -> Result<Response, RouteError>),
+    MethodNotAllowed(Vec<RequestType>),
+    Options(Vec<RequestType>),
+    NotFound,
+    Reject(RouteError),
+}
+impl<T: 'static + Send> RouteHandler<T> {
+    pub fn call(self, request: Request, data: &T) -> Result<Response, RouteError> {
+        match self {
+            RouteHandler::Route(handler) => handler(request, data),
+            RouteHandler::MethodNotAllowed(allowed) => {
+                Ok(Response::string("405 method not allowed")
+                    .status(ResponseStatusCode::MethodNotAllowed)
+                    .header("Allow", RouteHandler::<T>::allow_header(&allowed)))
+            }
+            RouteHandler::Options(allowed) => Ok(Response::new()
+                .status(ResponseStatusCode::NoContent)
+                .header("Allow", RouteHandler::<T>::allow_header(&allowed))),
+            RouteHandler::NotFound => Server::default_error(request, data),
+            RouteHandler::Reject(error) => Err(error),
         }
     }
-    fn add(&mut self, route: Route<T>) {
-        let handler_cat = match route.request_type {
-            RequestType::Get => &mut self.routes_get,
-            RequestType::Post => &mut self.routes_post,
-            RequestType::Put => &mut self.routes_put,
-            RequestType::Delete => &mut self.routes_delete,
-            RequestType::Any => &mut self.routes_any,
-        };
-        handler_cat.push(route);
+
+    fn allow_header(allowed: &[RequestType]) -> String {
+        allowed
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
     }
+}
+
+// The tests stand in for a client with a Unix socket pair.
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::BufReader;
+
+    use super::super::{
+        parser::ParseError, router::RouteStorage, Request, RequestType, Response, RouteError,
+        ServerConfig, TimedStream,
+    };
+    use super::{next_request, Route};
+
+    fn ok(_: Request, _: &()) -> Result<Response, RouteError> {
+        Ok(Response::new())
+    }
+
+    // Read one request sent as `input`. The bytes are handed to the
+    // stream up front, so the connection itself is never read.
+    fn read(
+        input: &str,
+        routes: &RouteStorage<()>,
+        config: &ServerConfig,
+    ) -> Result<Request, ParseError> {
+        let (connection, _peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut stream = TimedStream::new(Box::new(connection));
+        stream.unread(input.as_bytes().to_vec());
+        let mut reader = BufReader::new(stream);
+        next_request(&mut reader, routes, config).map(|(request, _)| request)
+    }
+
+    #[test]
+    fn routes_override_the_body_limit() {
+        let mut routes = RouteStorage::new();
+        routes.add(Route::create("/upload", RequestType::Post, ok).max_body_bytes(8));
+        routes.add(Route::create("/small", RequestType::Post, ok));
+        routes.prep().unwrap();
+        let mut config = ServerConfig::new();
+        config.limits.max_body_bytes = 4;
+        let post =
+            |path: &str| format!("POST {} HTTP/1.1\r\nContent-Length: 6\r\n\r\nabcdef", path);
 
-    fn prep(&mut self) {
-        self.routes_get.sort_by(|a, b| a.path.cmp(&b.path));
-        self.routes_post.sort_by(|a, b| a.path.cmp(&b.path));
-        self.routes_put.sort_by(|a, b| a.path.cmp(&b.path));
-        self.routes_delete.sort_by(|a, b| a.path.cmp(&b.path));
-        self.routes_any.sort_by(|a, b| a.path.cmp(&b.path));
+        let request = read(&post("/upload"), &routes, &config).unwrap();
+        assert_eq!(request.raw_body(), Some(&b"abcdef"[..]));
+        assert!(matches!(
+            read(&post("/small"), &routes, &config),
+            Err(ParseError::PayloadTooLarge)
+        ));
+        // Paths without a route keep the server's limit.
+        assert!(matches!(
+            read(&post("/missing"), &routes, &config),
+            Err(ParseError::PayloadTooLarge)
+        ));
     }
 }
@@ -0,0 +1,70 @@
+use std::io::{self, Write};
+
+pub type ResponseStream = Box<dyn FnOnce(&mut ChunkedWriter) -> io::Result<()>>;
+
+// Wraps the connection while a streamed response is written,
+// framing everything that passes through it as HTTP chunks.
+// Small writes are gathered so each chunk is a reasonable size.
+pub struct ChunkedWriter<'a> {
+    inner: &'a mut dyn Write,
+    buffer: Vec<u8>,
+    chunked: bool,
+}
+impl<'a> ChunkedWriter<'a> {
+    const CHUNK_SIZE: usize = 8192;
+
+    pub fn new(inner: &'a mut dyn Write) -> ChunkedWriter<'a> {
+        ChunkedWriter {
+            inner,
+            buffer: Vec::with_capacity(ChunkedWriter::CHUNK_SIZE),
+            chunked: true,
+        }
+    }
+
+    // Writes the body as is, for HTTP/1.0 clients that don't know
+    // chunked encoding. The body ends when the connection is closed.
+    pub fn raw(inner: &'a mut dyn Write) -> ChunkedWriter<'a> {
+        ChunkedWriter {
+            chunked: false,
+            ..ChunkedWriter::new(inner)
+        }
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if !self.chunked {
+            let written = self.inner.write_all(&self.buffer);
+            self.buffer.clear();
+            return written;
+        }
+        let mut chunk = format!("{:x}\r\n", self.buffer.len()).into_bytes();
+        chunk.append(&mut self.buffer);
+        chunk.extend_from_slice(b"\r\n");
+        self.inner.write_all(&chunk)
+    }
+
+    // Send whatever is left and the terminating zero sized chunk.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        if self.chunked {
+            self.inner.write_all(b"0\r\n\r\n")?;
+        }
+        self.inner.flush()
+    }
+}
+impl Write for ChunkedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= ChunkedWriter::CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}